@@ -0,0 +1,349 @@
+use serde_json::{json, Value};
+
+use v4l::control::{Description, Flags, MenuItem, Type};
+use v4l::frameinterval::FrameIntervalEnum;
+use v4l::framesize::FrameSizeEnum;
+use v4l::video::Capture;
+use v4l::{Device, FourCC};
+
+use crate::capture::SharedSettings;
+use crate::cli;
+use crate::controls;
+use crate::info;
+use crate::pixfmt;
+use crate::stepwise::{IntervalRange, SizeRange};
+
+/// Bumped whenever the API changes something, so the GUI knows to reread
+/// what it shows.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Revisions {
+    pub controls: u64,
+    pub format: u64,
+}
+
+/// An HTTP status with a message for the `error` field of the reply.
+pub struct ApiError(pub &'static str, pub String);
+
+fn bad_request(message: impl Into<String>) -> ApiError {
+    ApiError("400 Bad Request", message.into())
+}
+
+fn not_found(message: impl Into<String>) -> ApiError {
+    ApiError("404 Not Found", message.into())
+}
+
+fn rejected(message: impl Into<String>) -> ApiError {
+    ApiError("422 Unprocessable Entity", message.into())
+}
+
+fn device_error(er: std::io::Error) -> ApiError {
+    ApiError("500 Internal Server Error", er.to_string())
+}
+
+/// Whether `path` belongs to the API rather than the stream.
+pub fn is_api(path: &str) -> bool {
+    path == "/controls" || path.starts_with("/controls/") || path == "/formats" || path == "/format"
+}
+
+/// Answers an API request with a status and a JSON body. Clients that may
+/// not change anything (`may_change` false) only get to read.
+pub fn handle(method: &str, path: &str, body: &[u8], settings: &SharedSettings, may_change: bool)
+    -> (&'static str, Value) {
+    if method != "GET" && !may_change {
+        return ("403 Forbidden", json!({
+            "error": "changes are only accepted from this machine unless started with --http-api-remote"
+        }));
+    }
+
+    let result = match (method, path) {
+        ("GET", "/controls") => list_controls(settings),
+        ("GET", "/formats") => list_formats(settings),
+        ("POST", "/format") => set_format(settings, body),
+        (method, path) if path.starts_with("/controls/") => {
+            match path["/controls/".len()..].parse::<u32>() {
+                Ok(id) if method == "GET" => get_control(settings, id),
+                Ok(id) if method == "PUT" => set_control(settings, id, body),
+                Ok(_) => Err(ApiError("405 Method Not Allowed", String::from("use GET or PUT"))),
+                Err(_) => Err(not_found(format!("no control {}", &path["/controls/".len()..]))),
+            }
+        },
+        _ => Err(ApiError("405 Method Not Allowed", format!("{} isn't supported on {}", method, path))),
+    };
+
+    match result {
+        Ok(reply) => reply,
+        Err(ApiError(status, message)) => (status, json!({ "error": message })),
+    }
+}
+
+/// The device the capture thread is using, opened once more. V4L2 allows
+/// setting controls from any open handle.
+fn open(settings: &SharedSettings) -> Result<Device, ApiError> {
+    let id = *settings.id.lock().unwrap();
+    Device::new(id).map_err(|er| ApiError("503 Service Unavailable", format!("device {}: {}", id, er)))
+}
+
+fn list_controls(settings: &SharedSettings) -> Result<(&'static str, Value), ApiError> {
+    let dev = open(settings)?;
+    let controls = dev.query_controls().map_err(device_error)?;
+
+    Ok(("200 OK", Value::Array(controls.iter().map(|desc| describe(&dev, desc)).collect())))
+}
+
+fn get_control(settings: &SharedSettings, id: u32) -> Result<(&'static str, Value), ApiError> {
+    let dev = open(settings)?;
+    let desc = find_control(&dev, id)?;
+
+    Ok(("200 OK", describe(&dev, &desc)))
+}
+
+/// Sets a control from `{"value": ...}`. Booleans take true/false or 0/1,
+/// menus the item index, strings a string, buttons any value, everything
+/// else a number within the range.
+fn set_control(settings: &SharedSettings, id: u32, body: &[u8]) -> Result<(&'static str, Value), ApiError> {
+    let request: Value = serde_json::from_slice(body).map_err(|er| bad_request(er.to_string()))?;
+    let value = request.get("value").ok_or(bad_request("missing \"value\""))?;
+
+    let dev = open(settings)?;
+    let desc = find_control(&dev, id)?;
+
+    if desc.flags.intersects(Flags::READ_ONLY | Flags::DISABLED) {
+        return Err(rejected(format!("{} is read-only", desc.name)));
+    }
+
+    let value = match desc.typ {
+        Type::Boolean => {
+            let value = value.as_bool()
+                .or(value.as_i64().map(|value| value != 0))
+                .ok_or(bad_request("expected true or false"))?;
+            v4l::control::Value::Boolean(value)
+        },
+        Type::Integer | Type::Integer64 | Type::Menu | Type::IntegerMenu => {
+            let value = value.as_i64().ok_or(bad_request("expected an integer"))?;
+            if value < desc.minimum || value > desc.maximum {
+                return Err(bad_request(format!("{} is outside {}..={}", value, desc.minimum, desc.maximum)));
+            }
+            let listed = desc.items.as_ref()
+                .is_none_or(|items| items.iter().any(|(index, _)| *index as i64 == value));
+            if !listed {
+                return Err(bad_request(format!("{} has no menu item {}", desc.name, value)));
+            }
+            v4l::control::Value::Integer(value)
+        },
+        Type::Bitmask => {
+            let value = value.as_i64().ok_or(bad_request("expected an integer"))?;
+            if value < 0 || value & !desc.maximum != 0 {
+                return Err(bad_request(format!("{} has bits outside {:#x}", value, desc.maximum)));
+            }
+            v4l::control::Value::Integer(value)
+        },
+        Type::String => {
+            let value = value.as_str().ok_or(bad_request("expected a string"))?;
+            if (value.len() as i64) < desc.minimum || value.len() as i64 > desc.maximum {
+                return Err(bad_request(format!("{} takes {} to {} bytes", desc.name, desc.minimum, desc.maximum)));
+            }
+            v4l::control::Value::String(value.to_string())
+        },
+        Type::Button => v4l::control::Value::None,
+        typ => return Err(rejected(format!("{} controls can't be set", typ))),
+    };
+
+    controls::write(&dev, id, value)
+        .map_err(|er| rejected(format!("{}: {}", desc.name, er)))?;
+
+    settings.revisions.lock().unwrap().controls += 1;
+
+    // drivers may adjust the value, and flags of other controls can change
+    let desc = find_control(&dev, id)?;
+    Ok(("200 OK", describe(&dev, &desc)))
+}
+
+fn find_control(dev: &Device, id: u32) -> Result<Description, ApiError> {
+    dev.query_controls()
+        .map_err(device_error)?
+        .into_iter()
+        .find(|desc| desc.id == id)
+        .ok_or(not_found(format!("no control {}", id)))
+}
+
+fn describe(dev: &Device, desc: &Description) -> Value {
+    let readable = !desc.flags.intersects(Flags::WRITE_ONLY)
+        && !matches!(desc.typ, Type::CtrlClass | Type::Button);
+
+    let value = match controls::read(dev, desc) {
+        Ok(value) if readable => match value {
+            v4l::control::Value::Integer(value) => json!(value),
+            v4l::control::Value::Boolean(value) => json!(value),
+            v4l::control::Value::String(value) => json!(value),
+            _ => Value::Null,
+        },
+        _ => Value::Null,
+    };
+
+    let menu = desc.items.as_ref().map(|items| {
+        items.iter()
+            .map(|(index, item)| match item {
+                MenuItem::Name(name) => json!({ "value": index, "name": name }),
+                MenuItem::Value(value) => json!({ "value": index, "name": value.to_string() }),
+            })
+            .collect::<Vec<_>>()
+    });
+
+    json!({
+        "id": desc.id,
+        "name": desc.name,
+        "type": desc.typ.to_string(),
+        "minimum": desc.minimum,
+        "maximum": desc.maximum,
+        "step": desc.step,
+        "default": desc.default,
+        "flags": flag_names(desc.flags),
+        "menu": menu,
+        "value": value,
+    })
+}
+
+fn flag_names(flags: Flags) -> Vec<&'static str> {
+    [
+        (Flags::DISABLED, "disabled"),
+        (Flags::GRABBED, "grabbed"),
+        (Flags::READ_ONLY, "read-only"),
+        (Flags::UPDATE, "update"),
+        (Flags::INACTIVE, "inactive"),
+        (Flags::SLIDER, "slider"),
+        (Flags::WRITE_ONLY, "write-only"),
+        (Flags::VOLATILE, "volatile"),
+        (Flags::EXECUTE_ON_WRITE, "execute-on-write"),
+    ]
+    .into_iter()
+    .filter(|(flag, _)| flags.contains(*flag))
+    .map(|(_, name)| name)
+    .collect()
+}
+
+/// Every format with its frame sizes and intervals, plus the one in use.
+fn list_formats(settings: &SharedSettings) -> Result<(&'static str, Value), ApiError> {
+    let dev = open(settings)?;
+    let fmt = dev.format().map_err(device_error)?;
+    let params = dev.params().map_err(device_error)?;
+
+    let formats = info::formats(&dev).map_err(device_error)?;
+
+    Ok(("200 OK", json!({
+        "current": {
+            "fourcc": fourcc_str(&fmt.fourcc.repr),
+            "width": fmt.width,
+            "height": fmt.height,
+            "interval": [params.interval.numerator, params.interval.denominator],
+        },
+        "formats": formats,
+    })))
+}
+
+/// Switches format from `{"fourcc", "width", "height", "interval": [n, d]}`
+/// or `"fps"` in place of the interval. Left out fields stay as they are.
+/// The capture thread applies the change, so the reply is 202.
+fn set_format(settings: &SharedSettings, body: &[u8]) -> Result<(&'static str, Value), ApiError> {
+    let request: Value = serde_json::from_slice(body).map_err(|er| bad_request(er.to_string()))?;
+
+    let mut fourcc = *settings.fourcc.lock().unwrap();
+    let mut size = *settings.framesize.lock().unwrap();
+    let mut interval = *settings.frate.lock().unwrap();
+
+    if let Some(value) = request.get("fourcc") {
+        let value = value.as_str().ok_or(bad_request("fourcc must be a string"))?;
+        fourcc = cli::parse_fourcc(value).map_err(bad_request)?;
+    }
+
+    let number = |name: &str| -> Result<Option<u32>, ApiError> {
+        request.get(name)
+            .map(|value| value.as_u64()
+                .and_then(|value| u32::try_from(value).ok())
+                .ok_or(bad_request(format!("{} must be a positive integer", name))))
+            .transpose()
+    };
+
+    size.0 = number("width")?.unwrap_or(size.0);
+    size.1 = number("height")?.unwrap_or(size.1);
+
+    let dev = open(settings)?;
+
+    let listed = dev.enum_formats().map_err(device_error)?
+        .iter()
+        .any(|format| format.fourcc.repr == fourcc);
+    if !listed {
+        return Err(rejected(format!("the device has no {} format", fourcc_str(&fourcc))));
+    }
+    if !pixfmt::is_supported(&fourcc) {
+        return Err(rejected(format!("{} frames can't be shown", fourcc_str(&fourcc))));
+    }
+
+    size = valid_size(&dev, fourcc, size)?;
+
+    let fps = match (request.get("interval"), request.get("fps")) {
+        (Some(value), _) => {
+            let pair = value.as_array()
+                .filter(|pair| pair.len() == 2)
+                .and_then(|pair| Some((pair[0].as_u64()? as u32, pair[1].as_u64()? as u32)))
+                .filter(|(num, den)| *num > 0 && *den > 0)
+                .ok_or(bad_request("interval must be [numerator, denominator]"))?;
+            Some(pair.1 as f64 / pair.0 as f64)
+        },
+        (None, Some(value)) => Some(value.as_f64()
+            .filter(|fps| *fps > 0.0)
+            .ok_or(bad_request("fps must be a positive number"))?),
+        (None, None) => None,
+    };
+
+    if let Some(fps) = fps {
+        interval = valid_interval(&dev, fourcc, size, fps)?;
+    }
+
+    *settings.fourcc.lock().unwrap() = fourcc;
+    *settings.framesize.lock().unwrap() = size;
+    *settings.frate.lock().unwrap() = interval;
+    settings.revisions.lock().unwrap().format += 1;
+
+    Ok(("202 Accepted", json!({
+        "fourcc": fourcc_str(&fourcc),
+        "width": size.0,
+        "height": size.1,
+        "interval": [interval.0, interval.1],
+    })))
+}
+
+/// `size` if the device lists it, snapped onto the grid for stepwise sizes.
+fn valid_size(dev: &Device, fourcc: [u8; 4], size: (u32, u32)) -> Result<(u32, u32), ApiError> {
+    for framesize in dev.enum_framesizes(FourCC::new(&fourcc)).map_err(device_error)? {
+        match framesize.size {
+            FrameSizeEnum::Discrete(discrete) if (discrete.width, discrete.height) == size => return Ok(size),
+            FrameSizeEnum::Discrete(_) => (),
+            FrameSizeEnum::Stepwise(stepwise) => return Ok(SizeRange::new(&stepwise, framesize.typ).snap(size.0, size.1)),
+        }
+    }
+
+    Err(rejected(format!("{} has no {}x{} size", fourcc_str(&fourcc), size.0, size.1)))
+}
+
+/// The listed interval closest to `fps`, or the nearest step of a range.
+fn valid_interval(dev: &Device, fourcc: [u8; 4], size: (u32, u32), fps: f64) -> Result<(u32, u32), ApiError> {
+    let mut best: Option<(u32, u32)> = None;
+    let distance = |interval: (u32, u32)| (interval.1 as f64 / interval.0 as f64 - fps).abs();
+
+    for frameinterval in dev.enum_frameintervals(FourCC::new(&fourcc), size.0, size.1).map_err(device_error)? {
+        let candidate = match frameinterval.interval {
+            FrameIntervalEnum::Discrete(fraction) => (fraction.numerator, fraction.denominator),
+            FrameIntervalEnum::Stepwise(stepwise) => IntervalRange::new(&stepwise, frameinterval.typ).snap_fps(fps),
+        };
+
+        if candidate.0 > 0 && best.is_none_or(|best| distance(candidate) < distance(best)) {
+            best = Some(candidate);
+        }
+    }
+
+    best.ok_or(rejected(format!("no frame intervals for {} at {}x{}", fourcc_str(&fourcc), size.0, size.1)))
+}
+
+pub fn fourcc_str(fourcc: &[u8; 4]) -> String {
+    String::from_utf8_lossy(fourcc).trim_end().to_string()
+}
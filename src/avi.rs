@@ -0,0 +1,282 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// everything in front of the first frame: RIFF, hdrl list and the movi list header
+const HEADER_LEN: u32 = 224;
+
+/// Largest file written, index included. AVI 1.0 keeps sizes and offsets in
+/// 32 bits, and many players read them as signed.
+pub const MAX_FILE_LEN: u64 = i32::MAX as u64;
+
+/// Largest split size allowed, in MB, leaving room for the index.
+pub const MAX_SPLIT_MB: u64 = 2000;
+
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIIF_KEYFRAME: u32 = 0x10;
+
+/// Writes MJPEG frames into an AVI 1.0 file as they come from the camera,
+/// without re-encoding. The header is written again with the final frame
+/// count once `finish` is called, together with the idx1 index.
+pub struct AviWriter {
+    file: BufWriter<File>,
+    width: u32,
+    height: u32,
+    interval: (u32, u32),
+    /// (offset, size) of every frame chunk, for idx1.
+    index: Vec<(u32, u32)>,
+    movi_len: u32,
+    max_frame: u32,
+}
+
+impl AviWriter {
+    /// Creates the file at `path` for `width` x `height` frames, one every
+    /// `interval` (numerator/denominator) seconds.
+    pub fn create(path: &Path, width: u32, height: u32, interval: (u32, u32)) -> io::Result<Self> {
+        let mut writer = Self {
+            file: BufWriter::new(File::create(path)?),
+            width,
+            height,
+            interval: (interval.0.max(1), interval.1.max(1)),
+            index: Vec::new(),
+            movi_len: 0,
+            max_frame: 0,
+        };
+
+        // placeholder, rewritten by finish()
+        let header = writer.header(0);
+        writer.file.write_all(&header)?;
+
+        Ok(writer)
+    }
+
+    /// Appends a frame, unless the file would grow past `MAX_FILE_LEN`.
+    pub fn write_frame(&mut self, jpeg: &[u8]) -> io::Result<()> {
+        if !self.fits(jpeg.len()) {
+            return Err(io::Error::other(
+                format!("AVI file full, a {} byte frame would take it past {} bytes", jpeg.len(), MAX_FILE_LEN)));
+        }
+
+        let size = jpeg.len() as u32;
+
+        self.file.write_all(b"00dc")?;
+        self.file.write_all(&size.to_le_bytes())?;
+        self.file.write_all(jpeg)?;
+        // chunks are word aligned
+        if size % 2 == 1 {
+            self.file.write_all(&[0])?;
+        }
+
+        // idx1 offsets count from the 'movi' fourcc
+        self.index.push((4 + self.movi_len, size));
+        self.movi_len += 8 + size + size % 2;
+        self.max_frame = self.max_frame.max(size);
+
+        Ok(())
+    }
+
+    /// Size of the file so far, without the index.
+    pub fn file_len(&self) -> u64 {
+        HEADER_LEN as u64 + self.movi_len as u64
+    }
+
+    /// Whether a frame of `len` bytes still fits, with its index entry.
+    pub fn fits(&self, len: usize) -> bool {
+        let len = len as u64;
+        // chunk header and padding, then idx1 with one more entry
+        let total = self.file_len() + 8 + len + len % 2 + 8 + (self.index.len() as u64 + 1) * 16;
+        total <= MAX_FILE_LEN
+    }
+
+    /// Whether a frame of `len` bytes should go into a new file, the file
+    /// having reached `split_size` bytes or the AVI 1.0 limit.
+    pub fn is_full(&self, len: usize, split_size: u64) -> bool {
+        self.file_len() + len as u64 + 8 > split_size || !self.fits(len)
+    }
+
+    pub fn frames(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Appends the index and fills in the header.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.write_all(b"idx1")?;
+        self.file.write_all(&(self.index.len() as u32 * 16).to_le_bytes())?;
+
+        for (offset, size) in &self.index {
+            self.file.write_all(b"00dc")?;
+            self.file.write_all(&AVIIF_KEYFRAME.to_le_bytes())?;
+            self.file.write_all(&offset.to_le_bytes())?;
+            self.file.write_all(&size.to_le_bytes())?;
+        }
+
+        // everything after the RIFF size field
+        let riff_len = (self.file_len() + self.index.len() as u64 * 16) as u32;
+
+        self.file.seek(SeekFrom::Start(0))?;
+        let header = self.header(riff_len);
+        self.file.write_all(&header)?;
+        self.file.flush()
+    }
+
+    fn header(&self, riff_len: u32) -> Vec<u8> {
+        let frames = self.index.len() as u32;
+        let (num, denom) = self.interval;
+        let micro_sec_per_frame = (num as u64 * 1_000_000 / denom as u64) as u32;
+        let bytes_per_sec = (self.max_frame as u64 * denom as u64 / num as u64) as u32;
+
+        let mut h: Vec<u8> = Vec::with_capacity(HEADER_LEN as usize);
+
+        h.extend_from_slice(b"RIFF");
+        put_u32(&mut h, riff_len);
+        h.extend_from_slice(b"AVI ");
+
+        h.extend_from_slice(b"LIST");
+        put_u32(&mut h, 192);
+        h.extend_from_slice(b"hdrl");
+
+        // MainAVIHeader
+        h.extend_from_slice(b"avih");
+        put_u32(&mut h, 56);
+        put_u32(&mut h, micro_sec_per_frame);
+        put_u32(&mut h, bytes_per_sec);
+        put_u32(&mut h, 0); // padding granularity
+        put_u32(&mut h, AVIF_HASINDEX);
+        put_u32(&mut h, frames);
+        put_u32(&mut h, 0); // initial frames
+        put_u32(&mut h, 1); // streams
+        put_u32(&mut h, self.max_frame);
+        put_u32(&mut h, self.width);
+        put_u32(&mut h, self.height);
+        h.extend_from_slice(&[0; 16]);
+
+        h.extend_from_slice(b"LIST");
+        put_u32(&mut h, 116);
+        h.extend_from_slice(b"strl");
+
+        // AVIStreamHeader, rate / scale is the frame rate
+        h.extend_from_slice(b"strh");
+        put_u32(&mut h, 56);
+        h.extend_from_slice(b"vids");
+        h.extend_from_slice(b"MJPG");
+        put_u32(&mut h, 0); // flags
+        put_u32(&mut h, 0); // priority and language
+        put_u32(&mut h, 0); // initial frames
+        put_u32(&mut h, num);
+        put_u32(&mut h, denom);
+        put_u32(&mut h, 0); // start
+        put_u32(&mut h, frames);
+        put_u32(&mut h, self.max_frame);
+        put_u32(&mut h, u32::MAX); // default quality
+        put_u32(&mut h, 0); // sample size
+        h.extend_from_slice(&[0; 4]);
+        h.extend_from_slice(&(self.width as u16).to_le_bytes());
+        h.extend_from_slice(&(self.height as u16).to_le_bytes());
+
+        // BITMAPINFOHEADER
+        h.extend_from_slice(b"strf");
+        put_u32(&mut h, 40);
+        put_u32(&mut h, 40);
+        put_u32(&mut h, self.width);
+        put_u32(&mut h, self.height);
+        h.extend_from_slice(&1_u16.to_le_bytes()); // planes
+        h.extend_from_slice(&24_u16.to_le_bytes()); // bit count
+        h.extend_from_slice(b"MJPG");
+        put_u32(&mut h, self.width * self.height * 3);
+        h.extend_from_slice(&[0; 16]);
+
+        h.extend_from_slice(b"LIST");
+        put_u32(&mut h, 4 + self.movi_len);
+        h.extend_from_slice(b"movi");
+
+        debug_assert_eq!(h.len() as u32, HEADER_LEN);
+
+        h
+    }
+}
+
+fn put_u32(h: &mut Vec<u8>, v: u32) {
+    h.extend_from_slice(&v.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn header_and_index_layout() {
+        let path = std::env::temp_dir().join(format!("rustycamera-avi-layout-{}.avi", std::process::id()));
+        let mut writer = AviWriter::create(&path, 64, 48, (1, 30)).unwrap();
+        assert_eq!(writer.header(0).len(), HEADER_LEN as usize);
+
+        writer.write_frame(&[1, 2, 3]).unwrap();
+        writer.write_frame(&[4, 5, 6, 7]).unwrap();
+        assert_eq!(writer.frames(), 2);
+        writer.finish().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        // header, two chunks (the odd one padded), idx1 with two entries
+        assert_eq!(bytes.len(), 224 + 12 + 12 + 8 + 32);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(&bytes, 4), bytes.len() as u32 - 8);
+        assert_eq!(&bytes[8..12], b"AVI ");
+
+        // avih: microseconds per frame and total frames
+        assert_eq!(&bytes[24..28], b"avih");
+        assert_eq!(u32_at(&bytes, 32), 33_333);
+        assert_eq!(u32_at(&bytes, 44), AVIF_HASINDEX);
+        assert_eq!(u32_at(&bytes, 48), 2);
+        assert_eq!(u32_at(&bytes, 64), 64);
+        assert_eq!(u32_at(&bytes, 68), 48);
+
+        assert_eq!(&bytes[212..216], b"LIST");
+        assert_eq!(u32_at(&bytes, 216), 4 + 24);
+        assert_eq!(&bytes[220..224], b"movi");
+
+        assert_eq!(&bytes[224..228], b"00dc");
+        assert_eq!(u32_at(&bytes, 228), 3);
+        assert_eq!(&bytes[232..236], &[1, 2, 3, 0]);
+        assert_eq!(&bytes[236..240], b"00dc");
+        assert_eq!(u32_at(&bytes, 240), 4);
+
+        assert_eq!(&bytes[248..252], b"idx1");
+        assert_eq!(u32_at(&bytes, 252), 32);
+        for (entry, (offset, size)) in [(4, 3), (16, 4)].into_iter().enumerate() {
+            let at = 256 + entry * 16;
+            assert_eq!(&bytes[at..at + 4], b"00dc");
+            assert_eq!(u32_at(&bytes, at + 4), AVIIF_KEYFRAME);
+            assert_eq!(u32_at(&bytes, at + 8), offset);
+            assert_eq!(u32_at(&bytes, at + 12), size);
+            // offsets count from the 'movi' fourcc
+            assert_eq!(&bytes[220 + offset as usize..224 + offset as usize], b"00dc");
+        }
+    }
+
+    #[test]
+    fn refuses_frames_past_the_limit() {
+        let path = std::env::temp_dir().join(format!("rustycamera-avi-full-{}.avi", std::process::id()));
+        let mut writer = AviWriter::create(&path, 64, 48, (1, 30)).unwrap();
+
+        // pretend most of the file is written already
+        writer.movi_len = (MAX_FILE_LEN - HEADER_LEN as u64 - 1000) as u32;
+
+        assert!(writer.fits(100));
+        assert!(!writer.is_full(100, MAX_FILE_LEN));
+        assert!(writer.is_full(100, 1_000_000));
+        writer.write_frame(&[0; 100]).unwrap();
+
+        assert!(!writer.fits(1000));
+        assert!(writer.is_full(1000, u64::MAX));
+        assert!(writer.write_frame(&[0; 1000]).is_err());
+        assert_eq!(writer.frames(), 1);
+
+        drop(writer);
+        let _ = std::fs::remove_file(&path);
+    }
+}
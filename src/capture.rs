@@ -0,0 +1,689 @@
+use std::fmt;
+use std::io;
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use v4l::prelude::*;
+use v4l::video::Capture as _;
+use v4l::video::capture::Parameters;
+use v4l::buffer::{Flags as BufferFlags, Metadata, Type};
+use v4l::control::{self, Control};
+use v4l::format::Format;
+use v4l::io::traits::{CaptureStream, Stream};
+
+use zune_core::colorspace::ColorSpace;
+use zune_core::options::DecoderOptions;
+use zune_jpeg::JpegDecoder;
+
+use crate::bayer::{self, DebayerSettings};
+use crate::devices::{self, DeviceInfo};
+use crate::frame::{Frame, FrameSlot};
+use crate::api::Revisions;
+use crate::http::StreamHub;
+use crate::motion::{Motion, MotionWatcher};
+use crate::pixfmt;
+use crate::preroll::{Preroll, PrerollBuffer};
+use crate::record::{Recorder, Recording};
+use crate::snapshot::{self, Snapshot};
+use crate::timelapse::{self, Timelapse, TimelapseRunner};
+
+// errno for "No such device", returned once the camera is gone
+const ENODEV: i32 = 19;
+
+const RECONNECT_POLL: Duration = Duration::from_secs(1);
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(2);
+const IDLE_POLL: Duration = Duration::from_millis(200);
+
+/// Failed dequeues in a row before the device is treated as lost.
+const MAX_DEQUEUE_ERRORS: u32 = 50;
+/// How long dequeues may keep failing before the device is treated as lost,
+/// for cameras that time out rather than fail quickly.
+const MAX_STALL: Duration = Duration::from_secs(10);
+/// How long a dequeue waits for a frame, on top of twice the frame interval.
+const DEQUEUE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+pub enum CaptureError {
+    /// The device node went away (unplugged, USB reset, ...).
+    DeviceLost(io::Error),
+    /// An ioctl failed but the device is still there.
+    Io(io::Error),
+    /// Dequeuing kept failing or the stream couldn't be stopped, the
+    /// device has to be opened again.
+    Stalled(io::Error),
+    /// A single frame could not be decoded or was truncated.
+    CorruptFrame(String),
+    /// The pipeline doesn't know how to handle this pixel format.
+    UnsupportedFormat([u8; 4]),
+}
+
+impl CaptureError {
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, CaptureError::DeviceLost(_) | CaptureError::Stalled(_))
+    }
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::DeviceLost(er) => write!(f, "device lost: {}", er),
+            CaptureError::Io(er) => write!(f, "{}", er),
+            CaptureError::Stalled(er) => write!(f, "device stalled: {}", er),
+            CaptureError::CorruptFrame(msg) => write!(f, "corrupt frame: {}", msg),
+            CaptureError::UnsupportedFormat(fcc) => write!(f, "unsupported pixel format {}",
+                String::from_utf8_lossy(fcc)),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+impl From<io::Error> for CaptureError {
+    fn from(er: io::Error) -> Self {
+        if er.raw_os_error() == Some(ENODEV) {
+            CaptureError::DeviceLost(er)
+        } else {
+            CaptureError::Io(er)
+        }
+    }
+}
+
+/// An `MmapStream` that is safe to drop. v4l's own `Drop` stops the stream
+/// again and panics if that fails for any reason but ENODEV; a stream that
+/// can't be stopped is leaked here instead, buffers and file handle included.
+struct SafeStream<'a>(ManuallyDrop<MmapStream<'a>>);
+
+impl SafeStream<'_> {
+    fn new(dev: &Device, buffers: u32) -> io::Result<Self> {
+        Ok(Self(ManuallyDrop::new(MmapStream::with_buffers(dev, Type::VideoCapture, buffers)?)))
+    }
+
+    /// Stops and releases the stream. A stream that can't be stopped keeps
+    /// its buffers, so the device has to be opened again before its format
+    /// can change.
+    fn close(mut self) -> Result<(), CaptureError> {
+        match self.0.stop().map_err(CaptureError::from) {
+            Ok(()) => Ok(()),
+            Err(CaptureError::Io(er)) => Err(CaptureError::Stalled(er)),
+            Err(er) => Err(er),
+        }
+    }
+}
+
+impl<'a> Deref for SafeStream<'a> {
+    type Target = MmapStream<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for SafeStream<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Drop for SafeStream<'_> {
+    fn drop(&mut self) {
+        match self.0.stop() {
+            Err(er) if er.raw_os_error() != Some(ENODEV) => println!("Leaking a video stream that won't stop: {}", er),
+            // SAFETY: not used again, `self` is going away
+            _ => unsafe { ManuallyDrop::drop(&mut self.0) },
+        }
+    }
+}
+
+/// What the capture thread reports back to the GUI.
+#[derive(Debug, Default, Clone)]
+pub struct CaptureStatus {
+    /// Set when capture has stopped, the message is shown as a banner.
+    pub error: Option<String>,
+    /// Last recoverable error, cleared by the next good frame.
+    pub warning: Option<String>,
+    pub corrupt_frames: u64,
+    /// The device went away and the capture thread is waiting for it.
+    pub reconnecting: bool,
+    /// Bumped after every reconnect, so the GUI knows to reopen the device.
+    pub reconnects: u64,
+}
+
+/// Settings shared between the capture thread and the GUI.
+#[derive(Clone)]
+pub struct SharedSettings {
+    pub id: Arc<Mutex<usize>>,
+    pub frate: Arc<Mutex<(u32, u32)>>,
+    pub framesize: Arc<Mutex<(u32, u32)>>,
+    pub fourcc: Arc<Mutex<[u8; 4]>>,
+    pub status: Arc<Mutex<CaptureStatus>>,
+    pub debayer: Arc<Mutex<DebayerSettings>>,
+    pub recording: Arc<Mutex<Recording>>,
+    pub preroll: Arc<Mutex<Preroll>>,
+    pub timelapse: Arc<Mutex<Timelapse>>,
+    pub motion: Arc<Mutex<Motion>>,
+    pub snapshot: Arc<Mutex<Snapshot>>,
+    pub stream: Arc<StreamHub>,
+    pub revisions: Arc<Mutex<Revisions>>,
+    /// Set on exit, so open files get finished before the process ends.
+    pub quit: Arc<Mutex<bool>>,
+}
+
+pub struct Capture {
+    dev: Device,
+    id: usize,
+    fmt: Format,
+    parms: Parameters,
+    buffers: u32,
+    reconnect: bool,
+    identity: Option<DeviceInfo>,
+    saved_controls: Vec<Control>,
+    last_snapshot: Instant,
+    settings: SharedSettings,
+    frames: Arc<FrameSlot>,
+    recorder: Recorder,
+    preroll: PrerollBuffer,
+    timelapse: TimelapseRunner,
+    motion: MotionWatcher,
+}
+
+impl Capture {
+    pub fn new(dev: Device, id: usize, fmt: Format, buffers: u32, reconnect: bool,
+        settings: SharedSettings, frames: Arc<FrameSlot>) -> Self {
+        Self {
+            dev,
+            id,
+            fmt,
+            parms: Parameters::with_fps(30),
+            buffers,
+            reconnect,
+            identity: devices::device_info(id),
+            saved_controls: Vec::new(),
+            last_snapshot: Instant::now(),
+            settings,
+            frames,
+            recorder: Recorder::new(),
+            preroll: PrerollBuffer::new(),
+            timelapse: TimelapseRunner::new(),
+            motion: MotionWatcher::new(),
+        }
+    }
+
+    /// Runs the capture loop until the device is lost or can't be set up.
+    /// In reconnect mode a lost device is waited for instead.
+    pub fn run(&mut self) {
+        loop {
+            let result = self.capture_loop();
+
+            // close the file properly, a new one is started once frames flow again
+            self.recorder.stop(&self.settings.recording);
+
+            let er = match result {
+                Ok(()) => break,
+                Err(er) => er,
+            };
+
+            if !(self.reconnect && er.is_fatal()) {
+                println!("Capture stopped: {}", er);
+                self.settings.status.lock().unwrap().error = Some(er.to_string());
+                break;
+            }
+
+            println!("Capture interrupted: {}", er);
+            self.wait_for_device();
+
+            if self.quitting() {
+                break;
+            }
+        }
+
+        // no more frames are coming, a running timelapse or motion event ends here
+        self.timelapse.stop(&self.settings.timelapse);
+        self.motion.stop(&self.settings.motion, &self.settings.recording);
+        self.motion.wait();
+    }
+
+    fn quitting(&self) -> bool {
+        *self.settings.quit.lock().unwrap()
+    }
+
+    /// Polls until the lost camera shows up again (or the user picks another
+    /// one), then restores format, frame interval and control values.
+    fn wait_for_device(&mut self) {
+        {
+            let mut status = self.settings.status.lock().unwrap();
+            status.reconnecting = true;
+            status.warning = None;
+        }
+
+        self.frames.put(Frame::placeholder(self.fmt.width, self.fmt.height));
+
+        loop {
+            thread::sleep(RECONNECT_POLL);
+
+            if self.quitting() {
+                return;
+            }
+
+            let requested = *self.settings.id.lock().unwrap();
+            let switching = requested != self.id;
+
+            let found = match &self.identity {
+                Some(identity) if !switching => devices::find_device(identity),
+                _ => devices::device_info(requested),
+            };
+
+            let info = match found {
+                Some(info) => info,
+                None => continue,
+            };
+
+            let dev = match Device::new(info.index) {
+                Ok(dev) => dev,
+                Err(er) => {
+                    println!("Failed to open device {}: {}", info.index, er);
+                    continue;
+                }
+            };
+
+            println!("Device {} is back as {}", info.card, info.path);
+
+            self.dev = dev;
+            self.id = info.index;
+            self.identity = Some(info);
+            *self.settings.id.lock().unwrap() = self.id;
+
+            if switching {
+                self.saved_controls.clear();
+                if let Ok(fmt) = self.dev.format() {
+                    self.fmt = fmt;
+                }
+            } else if let Err(er) = self.restore_settings() {
+                println!("Failed to restore device settings: {}", er);
+                continue;
+            }
+
+            break;
+        }
+
+        let mut status = self.settings.status.lock().unwrap();
+        status.reconnecting = false;
+        status.error = None;
+        status.reconnects += 1;
+    }
+
+    /// Re-applies the last format, frame interval and control values to a
+    /// freshly reopened device.
+    fn restore_settings(&mut self) -> Result<(), CaptureError> {
+        self.fmt = self.dev.set_format(&self.fmt)?;
+        self.publish_format();
+
+        let (num, denom) = *self.settings.frate.lock().unwrap();
+        self.parms.interval.numerator = num;
+        self.parms.interval.denominator = denom;
+        if let Err(er) = self.dev.set_params(&self.parms) {
+            println!("Failed to set Parameters: {}", er);
+        }
+
+        // saved in query order, which puts auto modes before the values they gate
+        for ctrl in std::mem::take(&mut self.saved_controls) {
+            let id = ctrl.id;
+            if let Err(er) = self.dev.set_control(ctrl) {
+                println!("Failed to restore control {}: {}", id, er);
+            }
+        }
+
+        self.snapshot_controls();
+
+        Ok(())
+    }
+
+    /// Remembers the writable control values, so they can be put back after a
+    /// reconnect. The device is gone by the time we notice, so this has to be
+    /// done ahead of time.
+    fn snapshot_controls(&mut self) {
+        self.last_snapshot = Instant::now();
+
+        let descriptions = match self.dev.query_controls() {
+            Ok(descriptions) => descriptions,
+            Err(_) => return,
+        };
+
+        let skip = control::Flags::READ_ONLY | control::Flags::WRITE_ONLY | control::Flags::INACTIVE
+            | control::Flags::DISABLED | control::Flags::VOLATILE;
+
+        self.saved_controls = descriptions.iter()
+            .filter(|desc| !desc.flags.intersects(skip))
+            .filter(|desc| !matches!(desc.typ, control::Type::CtrlClass | control::Type::Button))
+            .filter_map(|desc| self.dev.control(desc.id).ok())
+            .collect();
+    }
+
+    fn capture_loop(&mut self) -> Result<(), CaptureError> {
+
+        self.fmt = self.dev.set_format(&self.fmt)?;
+        // The actual format chosen by the device driver may differ from what we
+        // requested! Print it out to get an idea of what is actually used now.
+        println!("Format in use:\n{}", self.fmt);
+
+        // keep the shared settings in sync with what the driver accepted,
+        // otherwise the loop below would keep re-applying the requested format
+        self.publish_format();
+
+        self.parms = self.dev.params()?;
+
+        println!("Parameters in use:\n{:?}", self.parms);
+
+        if self.reconnect {
+            self.snapshot_controls();
+        }
+
+        // Create the stream, which will internally 'allocate' (as in map) the
+        // number of requested buffers for us.
+        let mut stream = SafeStream::new(&self.dev, self.buffers)?;
+
+        // At this point, the stream is ready and all buffers are setup.
+        // We can now read frames (represented as buffers) by iterating through
+        // the stream. Once an error condition occurs, the iterator will return
+        // None.
+        let mut failures = 0_u32;
+        let mut failing_since = Instant::now();
+
+        loop {
+
+            if self.quitting() {
+                return Ok(());
+            }
+
+            stream = self.apply_settings(stream)?;
+
+            // a camera that stops sending frames without an error would
+            // otherwise block here for good
+            let interval = (self.parms.interval.numerator, self.parms.interval.denominator);
+            let frame_time = Duration::from_secs_f64(interval.0 as f64 / interval.1.max(1) as f64);
+            stream.set_timeout(DEQUEUE_TIMEOUT + frame_time.min(Duration::from_secs(60)) * 2);
+
+            let (buf, meta) = match stream.next() {
+                Ok(next) => next,
+                Err(er) => {
+                    if failures == 0 {
+                        failing_since = Instant::now();
+                    }
+                    let timed_out = er.kind() == io::ErrorKind::TimedOut;
+                    let er = match CaptureError::from(er) {
+                        CaptureError::Io(er) if failures + 1 >= MAX_DEQUEUE_ERRORS
+                            || failing_since.elapsed() >= MAX_STALL => CaptureError::Stalled(er),
+                        er => er,
+                    };
+                    if er.is_fatal() {
+                        println!("Dequeuing failed {} times in {:.1?}", failures + 1, failing_since.elapsed());
+                        return Err(er);
+                    }
+
+                    // one line per run of failures, and backing off while it lasts
+                    if failures == 0 {
+                        println!("Failed to dequeue buffer: {}", er);
+                    }
+                    failures += 1;
+                    self.settings.status.lock().unwrap().warning = Some(er.to_string());
+                    // a timeout has waited already
+                    if !timed_out {
+                        thread::sleep(Duration::from_millis(10 * failures as u64));
+                    }
+                    continue;
+                }
+            };
+
+            if failures > 0 {
+                println!("Dequeuing works again after {} failures", failures);
+                failures = 0;
+            }
+
+            // println!(
+            //     "Buffer size: {}, seq: {}, timestamp: {}",
+            //     buf.len(),
+            //     meta.sequence,
+            //     meta.timestamp
+            // );
+
+            // recorded as it came from the camera, before any decoding
+            if !meta.flags.contains(BufferFlags::ERROR) {
+                self.recorder.record(&self.settings.recording, &self.fmt, interval, payload(buf, meta));
+            }
+
+            match self.decode(buf, meta) {
+                Ok(frame) => {
+                    self.settings.status.lock().unwrap().warning = None;
+                    self.preroll.push(&self.settings.preroll, &self.settings.recording, &frame, interval);
+                    self.timelapse.frame(&self.settings.timelapse, &self.settings.recording, &frame);
+                    self.motion.frame(&self.settings.motion, &self.settings.recording, &frame, interval);
+                    // flagged frames are corrupt, the preview doesn't show them either
+                    if !frame.flags.contains(BufferFlags::ERROR) {
+                        snapshot::save_requested(&self.settings.snapshot, &frame);
+                    }
+                    self.settings.stream.offer(&frame);
+                    self.frames.put(frame);
+                },
+                Err(er) => {
+                    let mut status = self.settings.status.lock().unwrap();
+                    if let CaptureError::CorruptFrame(_) = er {
+                        status.corrupt_frames += 1;
+                    }
+                    status.warning = Some(er.to_string());
+                }
+            }
+
+            if self.reconnect && self.last_snapshot.elapsed() >= SNAPSHOT_INTERVAL {
+                self.snapshot_controls();
+            }
+
+            if let Some(wake) = self.timelapse.idle_until(&self.settings.timelapse) {
+                self.idle(&mut stream, wake)?;
+            }
+        }
+    }
+
+    /// Stops streaming until `wake`, for timelapses with long intervals.
+    /// Stopping the timelapse or quitting ends the pause early. The stream
+    /// isn't started again here: the next `next()` queues every buffer and
+    /// starts it, a bare STREAMON would leave it with a single buffer.
+    fn idle(&self, stream: &mut SafeStream, wake: SystemTime) -> Result<(), CaptureError> {
+        println!("Stream stopped until {}", timelapse::format_clock(wake));
+
+        if let Err(er) = stream.stop() {
+            let er = CaptureError::from(er);
+            if er.is_fatal() {
+                return Err(er);
+            }
+            println!("Failed to stop video stream: {}", er);
+            return Ok(());
+        }
+
+        while SystemTime::now() < wake && self.settings.timelapse.lock().unwrap().running && !self.quitting() {
+            thread::sleep(IDLE_POLL);
+        }
+
+        Ok(())
+    }
+
+    /// Picks up device, frame rate and format changes requested by the GUI.
+    /// Returns the stream to keep reading from, which is a new one whenever
+    /// the buffers had to be reallocated.
+    fn apply_settings<'a>(&mut self, mut stream: SafeStream<'a>) -> Result<SafeStream<'a>, CaptureError> {
+
+        let new_id = *self.settings.id.lock().unwrap();
+
+        if new_id != self.id {
+            match Device::new(new_id) {
+                Ok(new_dev) => {
+                    // the old device is left behind either way
+                    if let Err(er) = stream.close() {println!("Failed to stop video stream: {}", er);}
+
+                    self.dev = new_dev;
+                    self.id = new_id;
+                    self.identity = devices::device_info(new_id);
+                    self.saved_controls.clear();
+
+                    // start from whatever the new device is configured for,
+                    // the GUI reads the same values to rebuild its lists
+                    self.fmt = self.dev.format()?;
+                    self.parms = self.dev.params()?;
+                    println!("Switched to device {}\nFormat in use:\n{}", self.id, self.fmt);
+
+                    self.publish_format();
+                    *self.settings.frate.lock().unwrap() =
+                        (self.parms.interval.numerator, self.parms.interval.denominator);
+                    self.settings.status.lock().unwrap().error = None;
+
+                    stream = SafeStream::new(&self.dev, self.buffers)?;
+                },
+                Err(er) => {
+                    println!("Failed to open device {}: {}", new_id, er);
+                    *self.settings.id.lock().unwrap() = self.id;
+                }
+            }
+        }
+
+        let fcc = *self.settings.fourcc.lock().unwrap();
+        let (frate_num, frate_denom) = *self.settings.frate.lock().unwrap();
+        let (width, height) = *self.settings.framesize.lock().unwrap();
+
+        if self.parms.interval.denominator != frate_denom || self.parms.interval.numerator != frate_num {
+
+            self.parms.interval.denominator = frate_denom;
+            self.parms.interval.numerator = frate_num;
+
+            match stream.stop() {
+
+                Ok(_) => {
+                    match self.dev.set_params(&self.parms) {
+                        Ok(parms) => {println!("Parameters set to:\n{}", parms);},
+                        Err(er) => {println!("Failed to set Parameters: {}", er);}
+                    }
+                    if let Err(er) = stream.start() {println!("Failed to start video stream: {}", er);}
+                },
+                Err(er) => {
+                    let er = CaptureError::from(er);
+                    if er.is_fatal() {
+                        return Err(er);
+                    }
+                    println!("Failed to stop video stream: {}", er);
+                }
+            }
+        }
+
+        if self.fmt.width != width || self.fmt.height != height || !self.fmt.fourcc.repr.eq(&fcc) {
+
+            let mut fmt = self.fmt;
+            fmt.width = width;
+            fmt.height = height;
+            fmt.fourcc = v4l::FourCC::new(&fcc);
+
+            //must drop the old stream to set the new frame format
+            stream.close()?;
+
+            match self.dev.set_format(&fmt) {
+                Ok(fmt) => {
+                    // The actual format chosen by the device driver may differ from what we
+                    // requested! Print it out to get an idea of what is actually used now.
+                    println!("Format in use:\n{}", fmt);
+                    self.fmt = fmt;
+                },
+                Err(er) => {
+                    let er = CaptureError::from(er);
+                    if er.is_fatal() {
+                        return Err(er);
+                    }
+                    println!("Failed to write format: {}", er);
+                    self.settings.status.lock().unwrap().warning = Some(format!("Failed to write format: {}", er));
+                }
+            }
+
+            self.publish_format();
+
+            stream = SafeStream::new(&self.dev, self.buffers)?;
+        }
+
+        Ok(stream)
+    }
+
+    fn publish_format(&self) {
+        *self.settings.framesize.lock().unwrap() = (self.fmt.width, self.fmt.height);
+        *self.settings.fourcc.lock().unwrap() = self.fmt.fourcc.repr;
+    }
+
+    // To process the captured data, you can pass it somewhere else.
+    // If you want to modify the data or extend its lifetime, you have to
+    // copy it. This is a best-effort tradeoff solution that allows for
+    // zero-copy readers while enforcing a full clone of the data for
+    // writers.
+    fn decode(&self, buf: &[u8], meta: &Metadata) -> Result<Frame, CaptureError> {
+        let fmt = &self.fmt;
+        let buf = payload(buf, meta);
+
+        let (data, stride) = match &fmt.fourcc.repr {
+            fourcc if bayer::is_bayer(fourcc) => {
+                let (pattern, packing) = bayer::bayer_format(fourcc).unwrap();
+                let (width, height) = (fmt.width as usize, fmt.height as usize);
+
+                let samples = bayer::unpack(buf, fmt.stride as usize, width, height, packing)
+                    .ok_or(CaptureError::CorruptFrame(
+                        format!("short frame: {} bytes for {}x{}", buf.len(), width, height)))?;
+
+                let settings = *self.settings.debayer.lock().unwrap();
+                let data = bayer::debayer(&samples, width, height, packing.max(), pattern, &settings);
+
+                (data, fmt.width * 3)
+            },
+            fourcc if fourcc != b"MJPG" && pixfmt::is_supported(fourcc) => {
+                let expected = pixfmt::frame_len(fourcc, fmt.stride as usize, fmt.height as usize);
+                if buf.len() < expected {
+                    return Err(CaptureError::CorruptFrame(
+                        format!("short frame: {} of {} bytes", buf.len(), expected)));
+                }
+                (buf.to_vec(), fmt.stride)
+            },
+            b"MJPG" => {
+                let options = DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::RGBA);
+                // Decode the JPEG frame to RGBA
+                let mut decoder = JpegDecoder::new_with_options(io::Cursor::new(buf), options);
+                //let info = decoder.info().unwrap();
+                //eprintln!("{:?}", info);
+                //rgb vec
+                let data = decoder.decode()
+                    .map_err(|er| CaptureError::CorruptFrame(format!("{:?}", er)))?;
+
+                if data.len() < (fmt.width * fmt.height * 4) as usize {
+                    return Err(CaptureError::CorruptFrame(
+                        String::from("JPEG size doesn't match the frame size")));
+                }
+
+                (data, fmt.width * 4)
+            },
+            _ => return Err(CaptureError::UnsupportedFormat(fmt.fourcc.repr)),
+        };
+
+        Ok(Frame {
+            fourcc: fmt.fourcc.repr,
+            width: fmt.width,
+            height: fmt.height,
+            stride,
+            sequence: meta.sequence,
+            timestamp: meta.timestamp,
+            flags: meta.flags,
+            data,
+            jpeg: (&fmt.fourcc.repr == b"MJPG").then(|| buf.to_vec()),
+        })
+    }
+}
+
+/// The part of a capture buffer holding the frame. Some drivers leave
+/// bytesused at 0, so it's only trusted when set.
+fn payload<'a>(buf: &'a [u8], meta: &Metadata) -> &'a [u8] {
+    match meta.bytesused as usize {
+        0 => buf,
+        used => &buf[..used.min(buf.len())],
+    }
+}
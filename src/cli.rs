@@ -0,0 +1,370 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::avi;
+use crate::motion::MotionAction;
+use crate::preview::PreviewBackend;
+use crate::snapshot::ImageFormat;
+use crate::timelapse;
+
+pub const USAGE: &str = "\
+Usage: rustycamera [OPTIONS]
+
+Options:
+  -d, --device <N|PATH>     capture device index or /dev/videoN path
+                            [default: the last one used, else 0]
+  -f, --format <FOURCC>     pixel format, e.g. YUYV or MJPG [default: YUYV]
+  -s, --size <WxH>          frame size, e.g. 1280x720 [default: device format]
+  -r, --rate <NUM/DEN>      frame interval, e.g. 1/30 [default: 1/30]
+                            Without -f, -s and -r the last device starts
+                            with the format, size and rate it was left at
+  -b, --buffers <N>         number of mmap capture buffers [default: 4]
+      --no-gui              only open the preview window, without controls
+      --preview <sdl|embedded>
+                            show the preview in a window of its own or
+                            in a panel of the controls window [default: sdl]
+      --no-reconnect        stop capturing when the device is unplugged
+                            instead of waiting for it to come back
+      --snapshot-dir <DIR>  where snapshots are saved [default: .]
+      --snapshot-format <png|jpeg|ppm>
+                            snapshot image format [default: png]
+      --record-dir <DIR>    where recordings are saved, MJPG as .avi and
+                            raw YUV or grey formats as .y4m [default: .]
+      --split-size <MB>     start a new AVI file after this many
+                            megabytes, at most 2000 [default: 1000]
+      --y4m-420             record 4:2:2 streams such as YUYV as 4:2:0
+      --preroll <SECONDS>   keep the last SECONDS of frames in memory, so
+                            they can be saved after the fact [default: off]
+      --preroll-mb <MB>     memory limit of the pre-roll buffer [default: 500]
+      --timelapse <INTERVAL>
+                            take a timelapse shot every INTERVAL, e.g. 30s,
+                            5m or 1h; images go to a directory in the
+                            record dir, in the snapshot format
+      --timelapse-start <HH:MM>
+                            wait for this time before the first shot
+      --timelapse-end <HH:MM>
+                            stop the timelapse at this time
+      --timelapse-count <N> stop the timelapse after N shots
+      --timelapse-avi <FPS> write the timelapse as one MJPEG AVI played
+                            back at FPS instead of images
+      --timelapse-stop      stop streaming between shots, for intervals
+                            of 10 seconds or more
+      --motion <snapshot|clip|both>
+                            watch for motion and save a snapshot and/or a
+                            clip to the record dir when something moves;
+                            events are logged to motion-events.csv there
+      --http <[ADDR:]PORT>  serve the camera over HTTP: MJPEG on /stream
+                            (?fps=N to cap the rate), a JPEG on /snapshot
+                            and a JSON API for controls and formats
+                            (GET /controls, GET|PUT /controls/ID,
+                            GET /formats, POST /format); a bare port
+                            listens on all interfaces, but only clients
+                            on this machine may change controls or the
+                            format
+      --http-api-remote     let clients on other machines change controls
+                            and the format too
+      --profile <NAME>      apply a saved control profile of this device at
+                            start, including its format, size and rate
+      --profile-file <FILE> where profiles are kept
+                            [default: ~/.config/rustycamera/profiles.json]
+  -h, --help                print this help
+
+Keys in the preview window or panel:
+  S                         save a snapshot
+  R                         start or stop recording
+  Space                     pause or resume the preview
+  Mouse drag                add a motion detection region
+  Right click               clear the motion detection regions
+  Esc                       quit (preview window only)";
+
+pub struct Args {
+    pub device: Option<usize>,
+    pub fourcc: Option<[u8; 4]>,
+    pub size: Option<(u32, u32)>,
+    pub interval: Option<(u32, u32)>,
+    pub buffers: u32,
+    pub no_gui: bool,
+    pub preview: PreviewBackend,
+    pub no_reconnect: bool,
+    pub snapshot_dir: PathBuf,
+    pub snapshot_format: ImageFormat,
+    pub record_dir: PathBuf,
+    pub split_size: u64,
+    pub y4m_420: bool,
+    pub preroll: f64,
+    pub preroll_mb: u64,
+    pub timelapse: Option<Duration>,
+    pub timelapse_start: Option<SystemTime>,
+    pub timelapse_end: Option<SystemTime>,
+    pub timelapse_count: Option<u32>,
+    pub timelapse_avi: Option<u32>,
+    pub timelapse_stop: bool,
+    pub motion: Option<MotionAction>,
+    pub http: Option<String>,
+    pub http_api_remote: bool,
+    pub profile: Option<String>,
+    pub profile_file: Option<PathBuf>,
+    pub help: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            device: None,
+            fourcc: None,
+            size: None,
+            interval: None,
+            buffers: 4,
+            no_gui: false,
+            preview: PreviewBackend::Sdl,
+            no_reconnect: false,
+            snapshot_dir: PathBuf::from("."),
+            snapshot_format: ImageFormat::Png,
+            record_dir: PathBuf::from("."),
+            split_size: 1000,
+            y4m_420: false,
+            preroll: 0.0,
+            preroll_mb: 500,
+            timelapse: None,
+            timelapse_start: None,
+            timelapse_end: None,
+            timelapse_count: None,
+            timelapse_avi: None,
+            timelapse_stop: false,
+            motion: None,
+            http: None,
+            http_api_remote: false,
+            profile: None,
+            profile_file: None,
+            help: false,
+        }
+    }
+}
+
+/// Parses the command line arguments (without the program name).
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        // accept both "--opt value" and "--opt=value"
+        let (opt, inline) = match arg.split_once('=') {
+            Some((opt, value)) if opt.starts_with("--") => (opt.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+
+        let mut value = |name: &str| -> Result<String, String> {
+            match inline.clone().or_else(|| args.next()) {
+                Some(v) => Ok(v),
+                None => Err(format!("missing value for {}", name)),
+            }
+        };
+
+        match opt.as_str() {
+            "-d" | "--device" => parsed.device = Some(parse_device(&value(&opt)?)?),
+            "-f" | "--format" => parsed.fourcc = Some(parse_fourcc(&value(&opt)?)?),
+            "-s" | "--size" => parsed.size = Some(parse_size(&value(&opt)?)?),
+            "-r" | "--rate" => parsed.interval = Some(parse_interval(&value(&opt)?)?),
+            "-b" | "--buffers" => {
+                let v = value(&opt)?;
+                parsed.buffers = match v.parse::<u32>() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid buffer count: {}", v)),
+                };
+            },
+            "--no-gui" => parsed.no_gui = true,
+            "--preview" => {
+                let v = value(&opt)?;
+                parsed.preview = PreviewBackend::parse(&v)
+                    .ok_or(format!("invalid preview backend: {}", v))?;
+            },
+            "--no-reconnect" => parsed.no_reconnect = true,
+            "--snapshot-dir" => parsed.snapshot_dir = PathBuf::from(value(&opt)?),
+            "--snapshot-format" => {
+                let v = value(&opt)?;
+                parsed.snapshot_format = ImageFormat::parse(&v)
+                    .ok_or(format!("invalid snapshot format: {}", v))?;
+            },
+            "--record-dir" => parsed.record_dir = PathBuf::from(value(&opt)?),
+            "--split-size" => {
+                let v = value(&opt)?;
+                parsed.split_size = match v.parse::<u64>() {
+                    Ok(n) if n > 0 && n <= avi::MAX_SPLIT_MB => n,
+                    _ => return Err(format!("invalid split size: {} (1 to {} MB)", v, avi::MAX_SPLIT_MB)),
+                };
+            },
+            "--y4m-420" => parsed.y4m_420 = true,
+            "--preroll" => {
+                let v = value(&opt)?;
+                parsed.preroll = match v.parse::<f64>() {
+                    Ok(n) if n > 0.0 => n,
+                    _ => return Err(format!("invalid pre-roll length: {}", v)),
+                };
+            },
+            "--preroll-mb" => {
+                let v = value(&opt)?;
+                parsed.preroll_mb = match v.parse::<u64>() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid pre-roll size: {}", v)),
+                };
+            },
+            "--timelapse" => {
+                let v = value(&opt)?;
+                parsed.timelapse = Some(timelapse::parse_duration(&v)
+                    .ok_or(format!("invalid timelapse interval: {}", v))?);
+            },
+            "--timelapse-start" => {
+                let v = value(&opt)?;
+                parsed.timelapse_start = Some(timelapse::parse_clock(&v)
+                    .ok_or(format!("invalid timelapse start: {}", v))?);
+            },
+            "--timelapse-end" => {
+                let v = value(&opt)?;
+                parsed.timelapse_end = Some(timelapse::parse_clock(&v)
+                    .ok_or(format!("invalid timelapse end: {}", v))?);
+            },
+            "--timelapse-count" => {
+                let v = value(&opt)?;
+                parsed.timelapse_count = match v.parse::<u32>() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => return Err(format!("invalid timelapse count: {}", v)),
+                };
+            },
+            "--timelapse-avi" => {
+                let v = value(&opt)?;
+                parsed.timelapse_avi = match v.parse::<u32>() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => return Err(format!("invalid timelapse playback rate: {}", v)),
+                };
+            },
+            "--timelapse-stop" => parsed.timelapse_stop = true,
+            "--motion" => {
+                let v = value(&opt)?;
+                parsed.motion = Some(MotionAction::parse(&v)
+                    .ok_or(format!("invalid motion action: {}", v))?);
+            },
+            "--http" => parsed.http = Some(value(&opt)?),
+            "--http-api-remote" => parsed.http_api_remote = true,
+            "--profile" => parsed.profile = Some(value(&opt)?),
+            "--profile-file" => parsed.profile_file = Some(PathBuf::from(value(&opt)?)),
+            "-h" | "--help" => parsed.help = true,
+            _ => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+
+    if parsed.no_gui && parsed.preview == PreviewBackend::Embedded {
+        return Err(String::from("--no-gui needs the sdl preview"));
+    }
+
+    Ok(parsed)
+}
+
+/// Accepts a plain index ("2") or a device node path ("/dev/video2"). Symlinks
+/// such as /dev/v4l/by-id/... are resolved to the underlying node.
+pub fn parse_device(s: &str) -> Result<usize, String> {
+    if let Ok(index) = s.parse::<usize>() {
+        return Ok(index);
+    }
+
+    let path = Path::new(s).canonicalize().unwrap_or_else(|_| Path::new(s).to_path_buf());
+
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix("video"))
+        .and_then(|index| index.parse::<usize>().ok())
+        .ok_or(format!("invalid device: {}", s))
+}
+
+pub fn parse_fourcc(s: &str) -> Result<[u8; 4], String> {
+    let bytes = s.as_bytes();
+    if bytes.is_empty() || bytes.len() > 4 || !s.is_ascii() {
+        return Err(format!("invalid fourcc: {}", s));
+    }
+
+    // short codes like "Y16" or "GREY" are padded with spaces, as V4L2 does
+    let mut fcc = [b' '; 4];
+    fcc[..bytes.len()].copy_from_slice(bytes);
+
+    Ok(fcc)
+}
+
+pub fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    s.split_once(['x', 'X'])
+        .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+        .filter(|(w, h)| *w > 0 && *h > 0)
+        .ok_or(format!("invalid frame size: {}", s))
+}
+
+pub fn parse_interval(s: &str) -> Result<(u32, u32), String> {
+    s.split_once('/')
+        .and_then(|(n, d)| Some((n.parse::<u32>().ok()?, d.parse::<u32>().ok()?)))
+        .filter(|(n, d)| *n > 0 && *d > 0)
+        .ok_or(format!("invalid frame interval: {}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Result<Args, String> {
+        parse(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn devices() {
+        assert_eq!(parse_device("2"), Ok(2));
+        assert_eq!(parse_device("/dev/video3"), Ok(3));
+        assert!(parse_device("/dev/sda").is_err());
+        assert!(parse_device("video").is_err());
+    }
+
+    #[test]
+    fn fourccs() {
+        assert_eq!(parse_fourcc("MJPG"), Ok(*b"MJPG"));
+        assert_eq!(parse_fourcc("Y16"), Ok(*b"Y16 "));
+        assert!(parse_fourcc("").is_err());
+        assert!(parse_fourcc("YUYV2").is_err());
+        assert!(parse_fourcc("YÜV").is_err());
+    }
+
+    #[test]
+    fn sizes_and_intervals() {
+        assert_eq!(parse_size("1280x720"), Ok((1280, 720)));
+        assert_eq!(parse_size("640X480"), Ok((640, 480)));
+        assert!(parse_size("0x480").is_err());
+        assert!(parse_size("640").is_err());
+        assert!(parse_size("640x-1").is_err());
+
+        assert_eq!(parse_interval("1/30"), Ok((1, 30)));
+        assert_eq!(parse_interval("1001/30000"), Ok((1001, 30000)));
+        assert!(parse_interval("1/0").is_err());
+        assert!(parse_interval("30").is_err());
+    }
+
+    #[test]
+    fn options() {
+        let parsed = args("-d 1 --format=MJPG -s 640x480 -r 1/15 --split-size 2000 --http 8080").unwrap();
+        assert_eq!(parsed.device, Some(1));
+        assert_eq!(parsed.fourcc, Some(*b"MJPG"));
+        assert_eq!(parsed.size, Some((640, 480)));
+        assert_eq!(parsed.interval, Some((1, 15)));
+        assert_eq!(parsed.split_size, 2000);
+        assert_eq!(parsed.http.as_deref(), Some("8080"));
+        assert!(!parsed.http_api_remote);
+
+        let parsed = args("").unwrap();
+        assert_eq!(parsed.device, None);
+        assert_eq!(parsed.split_size, 1000);
+        assert_eq!(parsed.preview, PreviewBackend::Sdl);
+    }
+
+    #[test]
+    fn rejects() {
+        assert!(args("--split-size 0").is_err());
+        assert!(args("--split-size 2001").is_err());
+        assert!(args("--buffers 0").is_err());
+        assert!(args("--preview embedded --no-gui").is_err());
+        assert!(args("--device").is_err());
+        assert!(args("--bogus").is_err());
+    }
+}
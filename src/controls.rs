@@ -0,0 +1,158 @@
+use std::io;
+use std::mem;
+
+use v4l::control::{Control, Description, Flags, Type, Value};
+use v4l::v4l2;
+use v4l::v4l2::vidioc::_IOC_TYPE;
+use v4l::v4l_sys::{
+    v4l2_event, v4l2_event_subscription, v4l2_ext_control, v4l2_ext_controls,
+    V4L2_EVENT_CTRL, V4L2_EVENT_CTRL_CH_FLAGS, V4L2_EVENT_CTRL_CH_RANGE, V4L2_EVENT_CTRL_CH_VALUE,
+    V4L2_EVENT_SUB_FL_ALLOW_FEEDBACK,
+};
+use v4l::Device;
+
+// v4l has no event ioctls, these are _IOR('V', 89, struct v4l2_event) and
+// _IOW('V', 90, struct v4l2_event_subscription) from videodev2.h
+const VIDIOC_DQEVENT: _IOC_TYPE = ioc(2, 89, mem::size_of::<v4l2_event>());
+const VIDIOC_SUBSCRIBE_EVENT: _IOC_TYPE = ioc(1, 90, mem::size_of::<v4l2_event_subscription>());
+
+const fn ioc(dir: u32, nr: u32, size: usize) -> _IOC_TYPE {
+    ((dir << 30) | ((size as u32) << 16) | ((b'V' as u32) << 8) | nr) as _IOC_TYPE
+}
+
+/// What changed about a control, as reported by the driver.
+pub struct ControlEvent {
+    pub id: u32,
+    /// The new value. `None` for strings, which have to be read back.
+    pub value: Option<Value>,
+    pub value_changed: bool,
+    pub flags: Option<Flags>,
+    /// New minimum, maximum, step and default.
+    pub range: Option<(i64, i64, u64, i64)>,
+}
+
+/// Current value of the control `desc` describes. `Device::control` only
+/// reads integers, booleans and menus; strings, bitmasks and integer menus
+/// are read here. Buttons and class headings have no value.
+pub fn read(dev: &Device, desc: &Description) -> io::Result<Value> {
+    match desc.typ {
+        Type::Button | Type::CtrlClass => Ok(Value::None),
+        Type::String => read_string(dev, desc.id, desc.maximum),
+        Type::Bitmask | Type::IntegerMenu => {
+            let mut ctrl = v4l2_ext_control { id: desc.id, ..unsafe { mem::zeroed() } };
+            get_ext_control(dev, &mut ctrl)?;
+
+            // bitmasks are unsigned 32 bit, integer menus hold the item index
+            let value = unsafe { ctrl.__bindgen_anon_1.value };
+            Ok(Value::Integer(match desc.typ {
+                Type::Bitmask => value as u32 as i64,
+                _ => value as i64,
+            }))
+        },
+        _ => dev.control(desc.id).map(|control| control.value),
+    }
+}
+
+/// Current value of string control `id`, at most `max_len` bytes long.
+pub fn read_string(dev: &Device, id: u32, max_len: i64) -> io::Result<Value> {
+    // room for the longest string plus its terminating NUL
+    let mut buf = vec![0_u8; max_len.max(0) as usize + 1];
+    let mut ctrl = v4l2_ext_control { id, ..unsafe { mem::zeroed() } };
+    ctrl.size = buf.len() as u32;
+    ctrl.__bindgen_anon_1.string = buf.as_mut_ptr() as *mut std::os::raw::c_char;
+
+    get_ext_control(dev, &mut ctrl)?;
+
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    Ok(Value::String(String::from_utf8_lossy(&buf[..len]).into_owned()))
+}
+
+/// Sets control `id`. Strings get the terminating NUL the driver expects,
+/// which `Device::set_control` leaves out, cutting off the last character.
+pub fn write(dev: &Device, id: u32, value: Value) -> io::Result<()> {
+    let value = match value {
+        Value::String(s) => Value::String(s + "\0"),
+        value => value,
+    };
+
+    dev.set_control(Control { id, value })
+}
+
+fn get_ext_control(dev: &Device, ctrl: &mut v4l2_ext_control) -> io::Result<()> {
+    let mut ctrls = v4l2_ext_controls {
+        count: 1,
+        controls: ctrl,
+        ..unsafe { mem::zeroed() }
+    };
+
+    unsafe {
+        v4l2::ioctl(
+            dev.handle().fd(),
+            v4l2::vidioc::VIDIOC_G_EXT_CTRLS,
+            &mut ctrls as *mut _ as *mut std::os::raw::c_void,
+        )
+    }
+}
+
+/// Asks for an event whenever control `id` changes value, flags or range,
+/// including the changes made through `dev` itself, so values the driver
+/// adjusted come back too. Fails on drivers without control events.
+pub fn subscribe(dev: &Device, id: u32) -> io::Result<()> {
+    let mut sub = v4l2_event_subscription {
+        type_: V4L2_EVENT_CTRL,
+        id,
+        flags: V4L2_EVENT_SUB_FL_ALLOW_FEEDBACK,
+        ..unsafe { mem::zeroed() }
+    };
+
+    unsafe {
+        v4l2::ioctl(
+            dev.handle().fd(),
+            VIDIOC_SUBSCRIBE_EVENT,
+            &mut sub as *mut _ as *mut std::os::raw::c_void,
+        )
+    }
+}
+
+/// The next pending control event, without waiting; devices are opened
+/// non-blocking, so an empty queue is ENOENT. Only control events are ever
+/// subscribed to.
+pub fn next_event(dev: &Device) -> io::Result<Option<ControlEvent>> {
+    let mut event: v4l2_event = unsafe { mem::zeroed() };
+
+    let result = unsafe {
+        v4l2::ioctl(
+            dev.handle().fd(),
+            VIDIOC_DQEVENT,
+            &mut event as *mut _ as *mut std::os::raw::c_void,
+        )
+    };
+
+    match result {
+        Ok(()) => (),
+        Err(er) if er.raw_os_error() == Some(libc::ENOENT) => return Ok(None),
+        Err(er) => return Err(er),
+    }
+
+    let ctrl = unsafe { event.u.ctrl };
+    let typ = Type::try_from(ctrl.type_).ok();
+
+    let value = unsafe {
+        match typ {
+            Some(Type::Boolean) => Some(Value::Boolean(ctrl.__bindgen_anon_1.value != 0)),
+            Some(Type::Integer64) => Some(Value::Integer(ctrl.__bindgen_anon_1.value64)),
+            Some(Type::Bitmask) => Some(Value::Integer(ctrl.__bindgen_anon_1.value as u32 as i64)),
+            Some(Type::Integer | Type::Menu | Type::IntegerMenu) => Some(Value::Integer(ctrl.__bindgen_anon_1.value as i64)),
+            _ => None,
+        }
+    };
+
+    Ok(Some(ControlEvent {
+        id: event.id,
+        value,
+        value_changed: ctrl.changes & V4L2_EVENT_CTRL_CH_VALUE != 0,
+        flags: (ctrl.changes & V4L2_EVENT_CTRL_CH_FLAGS != 0).then(|| Flags::from_bits_truncate(ctrl.flags)),
+        range: (ctrl.changes & V4L2_EVENT_CTRL_CH_RANGE != 0).then_some((
+            ctrl.minimum as i64, ctrl.maximum as i64, ctrl.step as u64, ctrl.default_value as i64)),
+    }))
+}
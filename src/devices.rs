@@ -0,0 +1,113 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use v4l::video::Capture;
+
+/// Nodes whose open failure has been reported. Reconnecting probes every
+/// node each poll, so a failure is only printed until the node opens again.
+static OPEN_FAILURES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub index: usize,
+    pub path: String,
+    pub card: String,
+    pub driver: String,
+    pub bus: String,
+    pub serial: Option<String>,
+}
+
+impl DeviceInfo {
+    pub fn label(&self) -> String {
+        format!("{} ({}, {}) - {}", self.card, self.driver, self.bus, self.path)
+    }
+
+    /// Whether `other` is the same physical camera, possibly under a new
+    /// /dev/videoN index. The serial number survives moving the camera to
+    /// another port; without one the bus position is the best we have.
+    pub fn same_device(&self, other: &DeviceInfo) -> bool {
+        if self.card != other.card {
+            return false;
+        }
+
+        match (&self.serial, &other.serial) {
+            (Some(serial), Some(other_serial)) => serial == other_serial,
+            _ => self.bus == other.bus,
+        }
+    }
+}
+
+/// Lists the /dev/video* nodes that can actually capture frames. Metadata
+/// nodes (e.g. the second node of most UVC cameras) report no capture formats
+/// and are left out.
+pub fn enum_capture_devices() -> Vec<DeviceInfo> {
+    let mut devices: Vec<DeviceInfo> = Vec::new();
+
+    for node in v4l::context::enum_devices() {
+        if let Some(info) = probe(node.path(), node.index()) {
+            devices.push(info);
+        }
+    }
+
+    devices.sort_by_key(|d| d.index);
+
+    devices
+}
+
+pub fn device_info(index: usize) -> Option<DeviceInfo> {
+    probe(Path::new(&format!("/dev/video{}", index)), index)
+}
+
+/// Looks for `device` among the currently connected capture devices.
+pub fn find_device(device: &DeviceInfo) -> Option<DeviceInfo> {
+    enum_capture_devices().into_iter().find(|d| device.same_device(d))
+}
+
+fn probe(path: &Path, index: usize) -> Option<DeviceInfo> {
+    let dev = match v4l::Device::with_path(path) {
+        Ok(dev) => dev,
+        Err(er) => {
+            let path = path.display().to_string();
+            let mut failures = OPEN_FAILURES.lock().unwrap();
+            if !failures.contains(&path) {
+                println!("Couldn't open {}: {}", path, er);
+                failures.push(path);
+            }
+            return None;
+        }
+    };
+
+    OPEN_FAILURES.lock().unwrap().retain(|failed| failed.as_str() != path.as_os_str());
+
+    let caps = dev.query_caps().ok()?;
+
+    match dev.enum_formats() {
+        Ok(formats) if !formats.is_empty() => (),
+        _ => return None,
+    }
+
+    Some(DeviceInfo {
+        index,
+        path: path.display().to_string(),
+        card: caps.card,
+        driver: caps.driver,
+        bus: caps.bus,
+        serial: read_serial(index),
+    })
+}
+
+// USB devices expose their serial number on the usb_device node, which is the
+// parent of the interface the video node is bound to.
+fn read_serial(index: usize) -> Option<String> {
+    let path = format!("/sys/class/video4linux/video{}/device/../serial", index);
+    let serial = fs::read_to_string(path).ok()?;
+    let serial = serial.trim();
+
+    if serial.is_empty() {
+        None
+    } else {
+        Some(serial.to_string())
+    }
+}
@@ -0,0 +1,103 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use v4l::buffer::Flags;
+use v4l::timestamp::Timestamp;
+
+/// A single captured frame as handed from the capture thread to the renderer.
+///
+/// `fourcc` is the pixel format delivered by the device. Compressed formats
+/// are decoded before sending, so for `MJPG` the payload holds RGBA pixels
+/// and `stride` is `width * 4`. Raw Bayer formats are demosaiced, leaving
+/// packed RGB with a `stride` of `width * 3`. Planar formats keep all their
+/// planes in `data`, with `stride` giving the bytes per line of the first one.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub fourcc: [u8; 4],
+    pub width: u32,
+    pub height: u32,
+    pub stride: u32,
+    pub sequence: u32,
+    pub timestamp: Timestamp,
+    pub flags: Flags,
+    pub data: Vec<u8>,
+    /// The compressed frame as the camera sent it, for `MJPG`.
+    pub jpeg: Option<Vec<u8>>,
+}
+
+impl Frame {
+    pub fn fourcc_str(&self) -> &str {
+        std::str::from_utf8(&self.fourcc).unwrap_or("????")
+    }
+
+    /// A dark grey YUYV frame with diagonal stripes, shown while there is no
+    /// device to capture from.
+    pub fn placeholder(width: u32, height: u32) -> Self {
+        let stride = width * 2;
+        let mut data = vec![0_u8; (stride * height) as usize];
+
+        for (y, row) in data.chunks_mut(stride as usize).enumerate() {
+            for (x, pixel) in row.chunks_mut(2).enumerate() {
+                let stripe = (x + y) / 32 % 2 == 0;
+                pixel[0] = if stripe { 64 } else { 40 };
+                pixel[1] = 128;
+            }
+        }
+
+        Self {
+            fourcc: *b"YUYV",
+            width,
+            height,
+            stride,
+            sequence: 0,
+            timestamp: Timestamp::default(),
+            flags: Flags::empty(),
+            data,
+            jpeg: None,
+        }
+    }
+}
+
+/// Single-slot "latest frame wins" handoff between the capture thread and a
+/// consumer. The producer never blocks: a frame that was not taken before the
+/// next one arrives is replaced and counted as dropped, so a stalled consumer
+/// costs at most one frame of memory.
+pub struct FrameSlot {
+    frame: Mutex<Option<Frame>>,
+    ready: Condvar,
+    dropped: AtomicU64,
+}
+
+impl FrameSlot {
+    pub fn new() -> Self {
+        Self {
+            frame: Mutex::new(None),
+            ready: Condvar::new(),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn put(&self, frame: Frame) {
+        let mut slot = self.frame.lock().unwrap();
+        if slot.replace(frame).is_some() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        self.ready.notify_one();
+    }
+
+    /// Waits up to `timeout` for a frame, so the caller can keep servicing its
+    /// own events while the camera is slow or stopped.
+    pub fn take_timeout(&self, timeout: Duration) -> Option<Frame> {
+        let slot = self.frame.lock().unwrap();
+        let (mut slot, _) = self.ready
+            .wait_timeout_while(slot, timeout, |frame| frame.is_none())
+            .unwrap();
+        slot.take()
+    }
+
+    /// Number of frames replaced before the consumer got to them.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
@@ -1,11 +1,36 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 use v4l::control::MenuItem;
 use v4l::frameinterval::FrameIntervalEnum;
+use v4l::framesize::FrameSizeEnum;
 //use v4l::prelude::*;
 use v4l::capability::Flags;
 use v4l::video::Capture;
 
+use crate::api::Revisions;
+use crate::avi;
+use crate::bayer::{self, DebayerSettings, Demosaic};
+use crate::capture::{CaptureStatus, SharedSettings};
+use crate::controls;
+use crate::devices::{self, DeviceInfo};
+use crate::frame::FrameSlot;
+use crate::http::StreamHub;
+use crate::info::{self, DeviceReport};
+use crate::motion::{Motion, MotionAction};
+use crate::pixfmt;
+use crate::preroll::Preroll;
+use crate::preview::Preview;
+use crate::profile::{self, Profile, ProfileControl, ProfileStore, ProfileValue, Rejection};
+use crate::record::Recording;
+use crate::snapshot::{self, ImageFormat, Snapshot};
+use crate::state::{GuiState, WindowGeometry};
+use crate::stepwise::{self, IntervalRange, SizeRange};
+use crate::timelapse::{self, Timelapse, TimelapseOutput};
+
 use catppuccin_egui::{FRAPPE, LATTE, MACCHIATO, MOCHA};
 use eframe::egui;
 
@@ -17,15 +42,125 @@ enum CatppuccinTheme {
     Mocha,
 }
 
+impl CatppuccinTheme {
+    fn from_name(name: &str) -> Self {
+        match name {
+            "Frappe" => Self::Frappe,
+            "Latte" => Self::Latte,
+            "Macchiato" => Self::Macchiato,
+            _ => Self::Mocha,
+        }
+    }
+}
+
+/// Zoom factors offered for the UI, on top of the desktop's own scaling.
+const UI_SCALES: [f32; 8] = [0.75, 1.0, 1.25, 1.5, 1.75, 2.0, 2.5, 3.0];
+
 struct V4lControl {
     id: u32,
     typ: v4l::control::Type,
     name: String,
     minimum: i64,
     maximum: i64,
+    step: u64,
+    default: i64,
     flags: v4l::control::Flags,
     items: Option<Vec<(i64, String)>>,
     current: v4l::control::Value,
+    /// What's typed into a string control, until it's applied.
+    edit: String,
+    /// Recent values, while live readback is on.
+    history: VecDeque<i64>,
+}
+
+/// Samples kept for the sparklines.
+const HISTORY_LEN: usize = 100;
+
+impl V4lControl {
+    /// Whether the control holds a value that can be put back to its default.
+    fn resettable(&self) -> bool {
+        !self.flags.intersects(v4l::control::Flags::READ_ONLY | v4l::control::Flags::WRITE_ONLY)
+            && matches!(self.typ, v4l::control::Type::Boolean | v4l::control::Type::Integer
+                | v4l::control::Type::Integer64 | v4l::control::Type::Menu
+                | v4l::control::Type::IntegerMenu | v4l::control::Type::Bitmask)
+    }
+
+    fn is_default(&self) -> bool {
+        match self.current {
+            v4l::control::Value::Integer(v) => v == self.default,
+            v4l::control::Value::Boolean(v) => v == (self.default != 0),
+            _ => true,
+        }
+    }
+
+    /// The nearest value the driver accepts, `minimum` plus a multiple of
+    /// `step`.
+    fn snap(&self, value: i64) -> i64 {
+        stepwise::snap(value, self.minimum, self.maximum, self.step as i64)
+    }
+
+    /// Whether the driver may change the value on its own: read-only and
+    /// volatile controls, plus inactive ones, which is what an auto mode
+    /// drives (e.g. exposure under auto exposure).
+    fn is_live(&self) -> bool {
+        self.flags.intersects(v4l::control::Flags::READ_ONLY | v4l::control::Flags::VOLATILE
+            | v4l::control::Flags::INACTIVE)
+            && !self.flags.intersects(v4l::control::Flags::WRITE_ONLY)
+            && matches!(self.typ, v4l::control::Type::Integer | v4l::control::Type::Integer64
+                | v4l::control::Type::Boolean | v4l::control::Type::Menu)
+    }
+
+    /// Takes a value read back from the driver, keeping what's being typed
+    /// into a string control.
+    fn set_current(&mut self, value: v4l::control::Value) {
+        if let (v4l::control::Value::String(old), v4l::control::Value::String(new)) = (&self.current, &value) {
+            if self.edit == *old {
+                self.edit = new.clone();
+            }
+        }
+
+        self.current = value;
+    }
+
+    /// Why the driver would refuse `value` for a string control, if it would.
+    fn string_error(&self, value: &str) -> Option<String> {
+        let len = value.len() as i64;
+        let step = (self.step as i64).max(1);
+
+        if len < self.minimum || len > self.maximum {
+            Some(format!("{} to {} bytes", self.minimum, self.maximum))
+        } else if (len - self.minimum) % step != 0 {
+            Some(format!("length must be {} plus a multiple of {}", self.minimum, step))
+        } else {
+            None
+        }
+    }
+}
+
+/// A small line graph of `history`, scaled to its own range. Hovering shows
+/// the range.
+fn sparkline(ui: &mut egui::Ui, history: &VecDeque<i64>) {
+    let (rect, response) = ui.allocate_exact_size(egui::vec2(100., 16.), egui::Sense::hover());
+
+    let (lo, hi) = history.iter().fold((i64::MAX, i64::MIN), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+    let span = (hi - lo).max(1) as f32;
+
+    let points: Vec<egui::Pos2> = history.iter()
+        .enumerate()
+        .map(|(i, &v)| egui::pos2(
+            rect.left() + rect.width() * i as f32 / (HISTORY_LEN - 1) as f32,
+            rect.bottom() - rect.height() * (v - lo) as f32 / span))
+        .collect();
+
+    let stroke = egui::Stroke::new(1.0, ui.visuals().text_color());
+    ui.painter().add(egui::Shape::line(points, stroke));
+
+    response.on_hover_text(format!("{} to {} over the last {} samples", lo, hi, history.len()));
+}
+
+/// V4L2 control ids carry their class in the upper bits.
+fn control_class(id: u32) -> u32 {
+    id & 0x0fff_0000
 }
 
 pub struct GuiApp {
@@ -33,101 +168,417 @@ pub struct GuiApp {
     tab: u32,
     device: v4l::Device,
     controls: Vec<V4lControl>,
+    device_ind: usize,
+    list_devices: Vec<DeviceInfo>,
     fourcc_ind: usize,
     list_fourcc: Vec<([u8; 4], String)>,
     framesize_ind: usize,
     list_framesize: Vec<(u32, u32)>,
     frate_ind: usize,
     list_frate: Vec<(u32, u32)>,
+    framesize_range: Option<SizeRange>,
+    range_size: (u32, u32),
+    frate_range: Option<IntervalRange>,
+    range_fps: f64,
+    id_mtx: Arc<Mutex<usize>>,
     framesize_mtx: Arc<Mutex<(u32, u32)>>,
     frate_mtx: Arc<Mutex<(u32, u32)>>,
     fourcc_mtx: Arc<Mutex<[u8; 4]>>,
+    status_mtx: Arc<Mutex<CaptureStatus>>,
+    debayer_mtx: Arc<Mutex<DebayerSettings>>,
+    snapshot_mtx: Arc<Mutex<Snapshot>>,
+    snapshot_dir: String,
+    recording_mtx: Arc<Mutex<Recording>>,
+    record_dir: String,
+    preroll_mtx: Arc<Mutex<Preroll>>,
+    timelapse_mtx: Arc<Mutex<Timelapse>>,
+    motion_mtx: Arc<Mutex<Motion>>,
+    stream_hub: Arc<StreamHub>,
+    revisions_mtx: Arc<Mutex<Revisions>>,
+    revisions: Revisions,
+    timelapse_interval: f64,
+    timelapse_unit: u64,
+    timelapse_start: String,
+    timelapse_end: String,
+    profile_path: PathBuf,
+    profile_names: Vec<String>,
+    profile_ind: usize,
+    profile_name: String,
+    profile_last: Option<Result<String, String>>,
+    profile_rejected: Vec<Rejection>,
+    device_report: Option<Result<DeviceReport, String>>,
+    device_report_last: Option<Result<String, String>>,
+    /// Whether the device reports control changes as events.
+    control_events: bool,
+    readback: bool,
+    readback_hz: f64,
+    last_readback: Instant,
+    reconnects: u64,
+    /// Theme, tab, scale and window geometry, saved on exit.
+    state_mtx: Arc<Mutex<GuiState>>,
+    /// The preview panel, when it isn't a window of its own.
+    preview: Option<Preview>,
+    preview_width: f32,
 }
 
 impl GuiApp {
     //cc 
-    pub fn new(_cc: &eframe::CreationContext<'_>, 
-        id_mtx: Arc<Mutex<usize>>,
-        frate_mtx: Arc<Mutex<(u32, u32)>>,
-        framesize_mtx: Arc<Mutex<(u32, u32)>>,
-        fourcc_mtx: Arc<Mutex<[u8; 4]>>) -> Self {
+    pub fn new(cc: &eframe::CreationContext<'_>, 
+        settings: SharedSettings,
+        snapshot_mtx: Arc<Mutex<Snapshot>>,
+        profile_path: PathBuf,
+        state_mtx: Arc<Mutex<GuiState>>,
+        preview_frames: Option<Arc<FrameSlot>>) -> Self {
         // Customize egui here with cc.egui_ctx.set_fonts and cc.egui_ctx.set_visuals.
         // Restore app state using cc.storage (requires the "persistence" feature).
         // Use the cc.gl (a glow::Context) to create graphics shaders and buffers that you can use
         // for e.g. egui::PaintCallback.
-        let dev = v4l::Device::new(*id_mtx.lock().unwrap()).expect("Failed to open device");
-        let fmt = dev.format().expect("Failed to get device format");
-        
-        let mut list_fourcc: Vec<([u8; 4], String)> = Vec::new();
-        let mut fourcc_ind = 0_usize;
-        let mut list_framesize: Vec<(u32, u32)> = Vec::new();
-        let mut framesize_ind = 0_usize;
-        let mut list_frate: Vec<(u32, u32)> = Vec::new();
-        let frate_ind = 0_usize;
-
-        for formats in dev.enum_formats().expect("Failed to list device formats") {
-            
-            list_fourcc.push((formats.fourcc.repr, formats.description.clone()));
-            
-            if fmt.fourcc.repr == formats.fourcc.repr {
-                fourcc_ind = list_fourcc.len() - 1;
+        let id = *settings.id.lock().unwrap();
+        let snapshot_dir = snapshot_mtx.lock().unwrap().dir.display().to_string();
+        let record_dir = settings.recording.lock().unwrap().dir.display().to_string();
+        let (timelapse_interval, timelapse_unit, timelapse_start, timelapse_end) = {
+            let timelapse = settings.timelapse.lock().unwrap();
+            let secs = timelapse.interval.as_secs_f64();
+            let unit = [3600, 60].into_iter().find(|&unit| secs >= unit as f64).unwrap_or(1);
+            let clock = |time: Option<_>| time.map(|t| timelapse::format_clock(t)[..5].to_string()).unwrap_or_default();
+            (secs / unit as f64, unit, clock(timelapse.start), clock(timelapse.end))
+        };
+        let (theme, tab, preview_width) = {
+            let state = state_mtx.lock().unwrap();
+            cc.egui_ctx.set_zoom_factor(state.ui_scale);
+            (CatppuccinTheme::from_name(&state.theme), state.tab, state.preview_width)
+        };
+        let preview = preview_frames.map(|frames| Preview::new(&cc.egui_ctx, frames,
+            snapshot_mtx.clone(), settings.recording.clone(), settings.motion.clone()));
+        let dev = v4l::Device::new(id).expect("Failed to open device");
 
-                for framesize in dev.enum_framesizes(formats.fourcc)
-                    .expect("Failed to get device frame sizes") {
-                        
-                        for discrete in framesize.size.to_discrete() {
-                            
-                            list_framesize.push((discrete.width, discrete.height));
-                            
-                            if fmt.width == discrete.width && fmt.height == discrete.height {
-                                framesize_ind = list_framesize.len() - 1;
-                               
-                               for frameinterval in
-                                    dev.enum_frameintervals( 
-                                        framesize.fourcc, discrete.width, discrete.height)
-                                        .expect("Failed to list device frame rates") {
-                                   
-                                            match frameinterval.interval {
-                                                FrameIntervalEnum::Discrete(fraction) => {
-                                                    list_frate.push((fraction.numerator, fraction.denominator));
-                                                },
-                                                FrameIntervalEnum::Stepwise(_stepwise) => {
-                                                    println!("Stepwise Frame Rates not supported");
-                                                }
-                                            }
-                                }
-                            }
-                       }
-                }
-            }
-        }
+        let list_devices = devices::enum_capture_devices();
+        let device_ind = list_devices.iter().position(|d| d.index == id).unwrap_or(0);
        
         let ctrls = Vec::new();
 
         let mut this = Self {
-            theme: CatppuccinTheme::Mocha,
-            tab: 0,
+            theme,
+            tab,
             device: dev,
             controls: ctrls,
-            fourcc_ind,
-            list_fourcc,
-            framesize_ind,
-            list_framesize,
-            frate_ind,
-            list_frate,
-            framesize_mtx,
-            frate_mtx,
-            fourcc_mtx,
+            device_ind,
+            list_devices,
+            fourcc_ind: 0,
+            list_fourcc: Vec::new(),
+            framesize_ind: 0,
+            list_framesize: Vec::new(),
+            frate_ind: 0,
+            list_frate: Vec::new(),
+            framesize_range: None,
+            range_size: (0, 0),
+            frate_range: None,
+            range_fps: 0.,
+            id_mtx: settings.id,
+            framesize_mtx: settings.framesize,
+            frate_mtx: settings.frate,
+            fourcc_mtx: settings.fourcc,
+            status_mtx: settings.status,
+            debayer_mtx: settings.debayer,
+            snapshot_dir,
+            snapshot_mtx,
+            recording_mtx: settings.recording,
+            record_dir,
+            preroll_mtx: settings.preroll,
+            timelapse_mtx: settings.timelapse,
+            motion_mtx: settings.motion,
+            stream_hub: settings.stream,
+            revisions_mtx: settings.revisions,
+            revisions: Revisions::default(),
+            timelapse_interval,
+            timelapse_unit,
+            timelapse_start,
+            timelapse_end,
+            profile_path,
+            profile_names: Vec::new(),
+            profile_ind: 0,
+            profile_name: String::new(),
+            profile_last: None,
+            profile_rejected: Vec::new(),
+            device_report: None,
+            device_report_last: None,
+            control_events: false,
+            readback: false,
+            readback_hz: 4.0,
+            last_readback: Instant::now(),
+            reconnects: 0,
+            state_mtx,
+            preview,
+            preview_width,
         };
 
+        this.load_formats();
+        this.load_profile_names();
         this.get_device_ctrls().expect("get device controls");
 
         this
     }
 
+    /// Fills the format, frame size and frame rate lists from the device's
+    /// current format.
+    fn load_formats(&mut self) {
+        let fmt = match self.device.format() {
+            Ok(fmt) => fmt,
+            Err(er) => {
+                println!("Failed to get device format: {}", er);
+                return;
+            }
+        };
+        let interval = match self.device.params() {
+            Ok(parms) => (parms.interval.numerator, parms.interval.denominator),
+            Err(_) => (0, 0),
+        };
+
+        self.list_fourcc.clear();
+        self.fourcc_ind = 0;
+
+        for formats in self.device.enum_formats().unwrap_or_default() {
+
+            self.list_fourcc.push((formats.fourcc.repr, formats.description.clone()));
+
+            if fmt.fourcc.repr == formats.fourcc.repr {
+                self.fourcc_ind = self.list_fourcc.len() - 1;
+            }
+        }
+
+        self.load_framesizes(fmt.fourcc.repr, (fmt.width, fmt.height));
+
+        if let Some(size) = self.current_framesize() {
+            self.load_frates(fmt.fourcc.repr, size, interval);
+        }
+    }
+
+    /// Lists the frame sizes of `fourcc` and selects `current`, or the nearest
+    /// valid size when the driver reports a stepwise range.
+    fn load_framesizes(&mut self, fourcc: [u8; 4], current: (u32, u32)) {
+        self.list_framesize.clear();
+        self.framesize_ind = 0;
+        self.framesize_range = None;
+
+        let framesizes = match self.device.enum_framesizes(v4l::FourCC::new(&fourcc)) {
+            Ok(framesizes) => framesizes,
+            Err(er) => {
+                println!("Failed to get device frame sizes: {}", er);
+                return;
+            }
+        };
+
+        for framesize in framesizes {
+            match framesize.size {
+                FrameSizeEnum::Discrete(discrete) => {
+                    self.list_framesize.push((discrete.width, discrete.height));
+
+                    if current == (discrete.width, discrete.height) {
+                        self.framesize_ind = self.list_framesize.len() - 1;
+                    }
+                },
+                FrameSizeEnum::Stepwise(stepwise) => {
+                    let range = SizeRange::new(&stepwise, framesize.typ);
+                    self.range_size = range.snap(current.0, current.1);
+                    self.framesize_range = Some(range);
+                }
+            }
+        }
+    }
+
+    /// Lists the frame rates for `fourcc` at `size` and selects `current`, or
+    /// the nearest valid rate when the driver reports a stepwise range.
+    fn load_frates(&mut self, fourcc: [u8; 4], size: (u32, u32), current: (u32, u32)) {
+        self.list_frate.clear();
+        self.frate_ind = 0;
+        self.frate_range = None;
+
+        let frameintervals = match self.device.enum_frameintervals(
+            v4l::FourCC::new(&fourcc), size.0, size.1) {
+            Ok(frameintervals) => frameintervals,
+            Err(er) => {
+                println!("Failed to list device frame rates: {}", er);
+                return;
+            }
+        };
+
+        for frameinterval in frameintervals {
+            match frameinterval.interval {
+                FrameIntervalEnum::Discrete(fraction) => {
+                    self.list_frate.push((fraction.numerator, fraction.denominator));
+
+                    if current == (fraction.numerator, fraction.denominator) {
+                        self.frate_ind = self.list_frate.len() - 1;
+                    }
+                },
+                FrameIntervalEnum::Stepwise(stepwise) => {
+                    let range = IntervalRange::new(&stepwise, frameinterval.typ);
+                    let fps = if current.0 > 0 && current.1 > 0 {
+                        1.0 / stepwise::seconds(current)
+                    } else {
+                        range.fps_bounds().1
+                    };
+                    self.range_fps = 1.0 / stepwise::seconds(range.snap_fps(fps));
+                    self.frate_range = Some(range);
+                }
+            }
+        }
+    }
+
+    fn current_fourcc(&self) -> Option<[u8; 4]> {
+        self.list_fourcc.get(self.fourcc_ind).map(|format| format.0)
+    }
+
+    fn current_framesize(&self) -> Option<(u32, u32)> {
+        match self.framesize_range {
+            Some(_) => Some(self.range_size),
+            None => self.list_framesize.get(self.framesize_ind).copied(),
+        }
+    }
+
+    fn current_frate(&self) -> Option<(u32, u32)> {
+        match self.frate_range {
+            Some(range) => Some(range.snap_fps(self.range_fps)),
+            None => self.list_frate.get(self.frate_ind).copied(),
+        }
+    }
+
+    /// Selects the format, size and rate last handed to the capture thread,
+    /// for when the API changed them.
+    fn select_published(&mut self) {
+        let fcc = *self.fourcc_mtx.lock().unwrap();
+        let size = *self.framesize_mtx.lock().unwrap();
+        let frate = *self.frate_mtx.lock().unwrap();
+
+        if let Some(ind) = self.list_fourcc.iter().position(|format| format.0 == fcc) {
+            self.fourcc_ind = ind;
+        }
+
+        self.load_framesizes(fcc, size);
+        if let Some(size) = self.current_framesize() {
+            self.load_frates(fcc, size, frate);
+        }
+    }
+
+    /// Hands the selected format, size and rate to the capture thread.
+    fn publish_settings(&self) {
+        if let Some(fcc) = self.current_fourcc() {
+            *self.fourcc_mtx.lock().unwrap() = fcc;
+        }
+
+        if let Some(frate) = self.current_frate() {
+            *self.frate_mtx.lock().unwrap() = frate;
+        }
+
+        if let Some(size) = self.current_framesize() {
+            *self.framesize_mtx.lock().unwrap() = size;
+        }
+    }
+
+    /// Reopens the GUI side of the device and asks the capture thread to
+    /// switch over through `id_mtx`.
+    fn switch_device(&mut self, index: usize) {
+        let dev = match v4l::Device::new(index) {
+            Ok(dev) => dev,
+            Err(er) => {
+                println!("Failed to open device {}: {}", index, er);
+                return;
+            }
+        };
+
+        self.device = dev;
+        self.controls.clear();
+        self.device_report = None;
+        self.load_formats();
+        self.load_profile_names();
+
+        if self.get_device_ctrls().is_err() {
+            println!("Device {} is not a video capture device", index);
+        }
+
+        let mut id = self.id_mtx.lock().unwrap();
+        *id = index;
+    }
+
+    /// Lists the profiles saved for the current device.
+    fn load_profile_names(&mut self) {
+        let device = match self.list_devices.get(self.device_ind) {
+            Some(device) => device,
+            None => return,
+        };
+
+        match ProfileStore::load(&self.profile_path) {
+            Ok(store) => self.profile_names = store.names(device),
+            Err(er) => self.profile_last = Some(Err(er)),
+        }
+        self.profile_ind = self.profile_ind.min(self.profile_names.len().saturating_sub(1));
+    }
+
+    /// Saves the device's controls and the format in use as `name`. The file
+    /// is read again first, so profiles saved elsewhere meanwhile are kept.
+    fn save_profile(&mut self, name: &str) -> Result<String, String> {
+        let device = self.list_devices.get(self.device_ind).ok_or("no capture device")?;
+
+        let fcc = *self.fourcc_mtx.lock().unwrap();
+        let size = *self.framesize_mtx.lock().unwrap();
+        let frate = *self.frate_mtx.lock().unwrap();
+        let profile = Profile::capture(&self.device, fcc, size, frate)?;
+
+        let mut store = ProfileStore::load(&self.profile_path)?;
+        store.insert(device, name, profile);
+        store.save()?;
+
+        self.profile_names = store.names(device);
+        self.profile_ind = self.profile_names.iter().position(|n| n == name).unwrap_or(0);
+
+        Ok(format!("Saved {:?} to {}", name, self.profile_path.display()))
+    }
+
+    /// Applies profile `name`: the format goes to the capture thread, the
+    /// controls are written here and the ones the driver refused are kept for
+    /// display.
+    fn apply_profile(&mut self, name: &str) -> Result<String, String> {
+        let device = self.list_devices.get(self.device_ind).ok_or("no capture device")?;
+        let profile = profile::find(&self.profile_path, device, name)?;
+        let fcc = profile.fourcc()?;
+
+        *self.fourcc_mtx.lock().unwrap() = fcc;
+        *self.framesize_mtx.lock().unwrap() = (profile.width, profile.height);
+        *self.frate_mtx.lock().unwrap() = profile.interval;
+        self.select_published();
+
+        self.profile_rejected = profile.apply_controls(&self.device);
+        self.refresh_controls();
+
+        for rejection in &self.profile_rejected {
+            println!("profile {}: control rejected: {}", name, rejection);
+        }
+
+        Ok(format!("Applied {:?}", name))
+    }
+
+    fn delete_profile(&mut self, name: &str) -> Result<String, String> {
+        let device = self.list_devices.get(self.device_ind).ok_or("no capture device")?;
+
+        let mut store = ProfileStore::load(&self.profile_path)?;
+        store.remove(device, name);
+        store.save()?;
+
+        self.profile_names = store.names(device);
+        self.profile_ind = self.profile_ind.min(self.profile_names.len().saturating_sub(1));
+
+        Ok(format!("Deleted {:?}", name))
+    }
+
     fn get_device_ctrls(&mut self) -> Result< i32, i32> {
         
-        let caps = self.device.query_caps().expect("device query_caps");
+        let caps = match self.device.query_caps() {
+            Ok(caps) => caps,
+            Err(er) => {
+                println!("Couldn't query device capabilities: {}", er);
+                return Err(1);
+            }
+        };
         
         //let vid_cap:bool = (caps.capabilities & Flags::VIDEO_CAPTURE);
         //println!("capabilities VIDEO_CAPTURE: {}\n", vid_cap);
@@ -135,15 +586,21 @@ impl GuiApp {
             return Err(1);
         }
             
-        let controls = self.device.query_controls().unwrap();
+        let controls = match self.device.query_controls() {
+            Ok(controls) => controls,
+            Err(er) => {
+                println!("Couldn't query controls: {}", er);
+                return Err(1);
+            }
+        };
         
         for ctrl in controls {
             
             let mut value = v4l::control::Value::Integer(0);
 
             if !ctrl.flags.intersects(v4l::control::Flags::WRITE_ONLY) {
-                value = match self.device.control(ctrl.id) {
-                    Ok(val) => val.value,
+                value = match controls::read(&self.device, &ctrl) {
+                    Ok(val) => val,
                     Err(er) => {
                         println!("Couldn't get value for ctrl id {}: {}", ctrl.id, er);
                         v4l::control::Value::Integer(0)
@@ -176,12 +633,13 @@ impl GuiApp {
                 name: ctrl.name.clone(),
                 minimum: ctrl.minimum,
                 maximum: ctrl.maximum,
+                step: ctrl.step,
+                default: ctrl.default,
                 flags: ctrl.flags,
                 items: {
-                    if ctrl.items.is_some() {
+                    if let Some(items) = ctrl.items {
                         println!("menu items:");
                         let mut menu = Vec::new();
-                        let items = ctrl.items.expect("control items vector");
                         for item in items.iter() {
                             let m_value: i64;
                             let (v, m_item) = item;
@@ -192,9 +650,10 @@ impl GuiApp {
                                     menu.push((m_value, name.clone()));
                                 },
                                 MenuItem::Value(value) => {
-                                    m_value = *value;
-                                    println!("{}: {}", v, m_value);
-                                    menu.push((m_value, format!("{}", m_value)));
+                                    // integer menus are set by item index too
+                                    m_value = *v as i64;
+                                    println!("{}: {}", v, value);
+                                    menu.push((m_value, format!("{}", value)));
                                 }
                             }
                         }
@@ -203,6 +662,11 @@ impl GuiApp {
                         None
                     }
                 },
+                edit: match &value {
+                    v4l::control::Value::String(s) => s.clone(),
+                    _ => String::new(),
+                },
+                history: VecDeque::new(),
                 current: value,
             };
     
@@ -212,19 +676,115 @@ impl GuiApp {
 
         }
 
+        // follow changes made by other programs and by the camera itself
+        self.control_events = self.controls.iter()
+            .filter(|ctrl| ctrl.typ != v4l::control::Type::CtrlClass)
+            .all(|ctrl| controls::subscribe(&self.device, ctrl.id).is_ok());
+        if !self.control_events {
+            println!("Device doesn't send control events, controls are reread after changes");
+        }
+
         Ok(0)
     }
 
+    /// Applies the control changes the driver reported since the last frame.
+    fn poll_control_events(&mut self) {
+        if !self.control_events {
+            return;
+        }
+
+        loop {
+            let event = match controls::next_event(&self.device) {
+                Ok(Some(event)) => event,
+                Ok(None) => break,
+                Err(er) => {
+                    println!("Couldn't read control events: {}", er);
+                    self.control_events = false;
+                    break;
+                }
+            };
+
+            let ctrl = match self.controls.iter_mut().find(|ctrl| ctrl.id == event.id) {
+                Some(ctrl) => ctrl,
+                None => continue,
+            };
+
+            if let Some(flags) = event.flags {
+                ctrl.flags = flags;
+            }
+
+            if let Some((minimum, maximum, step, default)) = event.range {
+                (ctrl.minimum, ctrl.maximum, ctrl.step, ctrl.default) = (minimum, maximum, step, default);
+            }
+
+            if event.value_changed {
+                // string events don't carry the string
+                let value = match event.value {
+                    Some(value) => Ok(value),
+                    None => controls::read_string(&self.device, ctrl.id, ctrl.maximum),
+                };
+
+                match value {
+                    Ok(value) => ctrl.set_current(value),
+                    Err(er) => println!("Couldn't get value for ctrl id {}: {}", ctrl.id, er),
+                }
+            }
+        }
+    }
+
+    /// Rereads the controls the driver changes on its own and records their
+    /// history. Volatile values come without events, so this polls.
+    fn readback_controls(&mut self) {
+        self.last_readback = Instant::now();
+
+        for ctrl in self.controls.iter_mut().filter(|ctrl| ctrl.is_live()) {
+            let value = match self.device.control(ctrl.id) {
+                Ok(control) => control.value,
+                Err(_) => continue,
+            };
+
+            let sample = match value {
+                v4l::control::Value::Integer(v) => v,
+                v4l::control::Value::Boolean(v) => v as i64,
+                _ => continue,
+            };
+
+            if ctrl.history.len() == HISTORY_LEN {
+                ctrl.history.pop_front();
+            }
+            ctrl.history.push_back(sample);
+            ctrl.current = value;
+        }
+    }
+
+    /// Rereads every control, unless the driver reports changes as events.
+    fn refresh_controls(&mut self) {
+        if !self.control_events {
+            let _ = self.update_controls();
+        }
+    }
+
     fn update_controls(&mut self) -> Result<i32, i32> {
-        let q_ctrls = self.device.query_controls().expect("query controls");
+        let q_ctrls = match self.device.query_controls() {
+            Ok(q_ctrls) => q_ctrls,
+            Err(er) => {
+                println!("Couldn't query controls: {}", er);
+                return Err(1);
+            }
+        };
+
+        for ctrl in self.controls.iter_mut() {
+            let q_ctrl = match q_ctrls.iter().find(|q_ctrl| q_ctrl.id == ctrl.id) {
+                Some(q_ctrl) => q_ctrl,
+                None => continue,
+            };
 
-        for (pos, ctrl) in self.controls.iter_mut().enumerate() {
             let mut value = v4l::control::Value::Integer(0);
-            ctrl.flags = q_ctrls[pos].flags;
+            ctrl.flags = q_ctrl.flags;
 
             if !ctrl.flags.intersects(v4l::control::Flags::WRITE_ONLY) {
-                value = match self.device.control(ctrl.id) {
-                    Ok(val) => val.value,
+                value = match controls::read(&self.device, q_ctrl) {
+                    Ok(val) => val,
                     Err(er) => {
                         println!("Couldn't get value for ctrl id {}: {}", ctrl.id, er);
                         v4l::control::Value::Integer(0)
@@ -232,9 +792,9 @@ impl GuiApp {
                 };
             }
 
-            ctrl.current = value;
+            ctrl.set_current(value);
 
-         //   println!("{}", q_ctrls[pos]);
+         //   println!("{}", q_ctrl);
          //   match ctrl.current {
          //       v4l::control::Value::Integer(v) => {
          //           println!("value: Integer({})", v);
@@ -269,18 +829,83 @@ impl GuiApp {
 
                 let mut changed = false;
 
+                // resets are collected here and done after the loop, all at once
+                let mut reset_all = false;
+                let mut reset_class = None;
+                let mut reset_id = None;
+
                 ui.spacing_mut().slider_width = 300.;
 
                 ui.set_width(ui.available_width());
 
+                let changed_classes: Vec<u32> = self.controls.iter()
+                    .filter(|ctrl| ctrl.resettable() && !ctrl.is_default())
+                    .map(|ctrl| control_class(ctrl.id))
+                    .collect();
+
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(!changed_classes.is_empty(), egui::Button::new("Reset All Controls")).clicked() {
+                        reset_all = true;
+                    }
+
+                    ui.checkbox(&mut self.readback, "Live Readback");
+                    ui.add_enabled(self.readback, egui::DragValue::new(&mut self.readback_hz)
+                        .range(0.5..=30.0)
+                        .speed(0.1)
+                        .suffix(" Hz"));
+                });
+
                 for ctrl in self.controls.iter_mut() {
+                    let disabled = ctrl.flags.intersects(v4l::control::Flags::INACTIVE
+                        | v4l::control::Flags::DISABLED | v4l::control::Flags::GRABBED);
+
+                    // only offered where the value isn't the default already
+                    let can_reset = ctrl.resettable() && !ctrl.is_default() && !disabled;
+
                     match ctrl.typ {
                         v4l::control::Type::CtrlClass => {
+                            let class = control_class(ctrl.id);
+
                             ui.separator();
-                            ui.heading(ctrl.name.clone());
+                            ui.horizontal(|ui| {
+                                ui.heading(ctrl.name.clone());
+                                let response = ui.add_enabled(
+                                    changed_classes.contains(&class),
+                                    egui::Button::new("Reset All"));
+                                if response.clicked() {
+                                    reset_class = Some(class);
+                                }
+                            });
                         },
                         
                         v4l::control::Type::String => {
+                            let read_only = ctrl.flags.intersects(v4l::control::Flags::READ_ONLY);
+                            let max_len = ctrl.maximum.max(0) as usize;
+
+                            ui.horizontal(|ui| {
+                                let response = ui.add_enabled(
+                                    !disabled && !read_only,
+                                    egui::TextEdit::singleline(&mut ctrl.edit).char_limit(max_len));
+                                ui.label(ctrl.name.clone());
+
+                                let edited = !matches!(&ctrl.current, v4l::control::Value::String(s) if *s == ctrl.edit);
+                                let error = ctrl.string_error(&ctrl.edit);
+
+                                let entered = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                                let apply = ui.add_enabled(edited && error.is_none(), egui::Button::new("Apply").small());
+
+                                if (apply.clicked() || entered) && edited && error.is_none() {
+                                    let value = v4l::control::Value::String(ctrl.edit.clone());
+                                    if let Err(er) = controls::write(&self.device, ctrl.id, value) {
+                                        println!("Couldn't set {}: {}", ctrl.name, er);
+                                    }
+                                    changed = true;
+                                }
+
+                                if let (true, Some(er)) = (edited, error) {
+                                    ui.colored_label(ui.visuals().error_fg_color, er);
+                                }
+                            });
                         },
 
                         v4l::control::Type::Boolean => {
@@ -292,22 +917,25 @@ impl GuiApp {
                                 },
                             };
 
-                            let disabled = ctrl.flags.intersects(
-                                v4l::control::Flags::INACTIVE.union(v4l::control::Flags::DISABLED));
-
-                            let response = ui.add_enabled(
-                                !disabled,
-                                egui::Checkbox::new(&mut c_value, ctrl.name.clone()));
-                            if response.clicked() {
-                                //println!("control id {} changed to {}", ctrl.id, val);
-                                ctrl.current = v4l::control::Value::Boolean(c_value);
-                                let control = v4l::Control {
-                                    id: ctrl.id, 
-                                    value: v4l::control::Value::Boolean(c_value),
+                            ui.horizontal(|ui| {
+                                let response = ui.add_enabled(
+                                    !disabled,
+                                    egui::Checkbox::new(&mut c_value, ctrl.name.clone()));
+                                if response.clicked() {
+                                    //println!("control id {} changed to {}", ctrl.id, val);
+                                    ctrl.current = v4l::control::Value::Boolean(c_value);
+                                    let control = v4l::Control {
+                                        id: ctrl.id, 
+                                        value: v4l::control::Value::Boolean(c_value),
+                                    };
+                                    let _ = self.device.set_control(control);
+                                    changed = true;
                                 };
-                                let _ = self.device.set_control(control);
-                                changed = true;
-                            };
+
+                                if ui.add_enabled(can_reset, egui::Button::new("Reset").small()).clicked() {
+                                    reset_id = Some(ctrl.id);
+                                }
+                            });
                         },
 
                         v4l::control::Type::U8 |
@@ -323,29 +951,77 @@ impl GuiApp {
                                 },
                             };
 
-                            let disabled = ctrl.flags.intersects(
-                                v4l::control::Flags::INACTIVE.union(v4l::control::Flags::DISABLED));
-                            
-                            let response = ui.add_enabled(
-                                !disabled,
-                                egui::Slider::new(&mut c_value, ctrl.minimum..=ctrl.maximum)
-                                    .text(ctrl.name.clone()));
-                            if response.changed() {
-                                //println!("control id {} changed to {}", ctrl.id, val);
-                                ctrl.current = v4l::control::Value::Integer(c_value);
-                                let control = v4l::Control {
-                                    id: ctrl.id, 
-                                    value: v4l::control::Value::Integer(c_value),
+                            ui.horizontal(|ui| {
+                                let response = ui.add_enabled(
+                                    !disabled,
+                                    egui::Slider::new(&mut c_value, ctrl.minimum..=ctrl.maximum)
+                                        .text(ctrl.name.clone()));
+                                if response.changed() {
+                                    // values between steps are rejected or rounded by the driver
+                                    let c_value = ctrl.snap(c_value);
+
+                                    //println!("control id {} changed to {}", ctrl.id, val);
+                                    ctrl.current = v4l::control::Value::Integer(c_value);
+                                    let control = v4l::Control {
+                                        id: ctrl.id, 
+                                        value: v4l::control::Value::Integer(c_value),
+                                    };
+                                    let _ = self.device.set_control(control);
+                                    changed = true;
                                 };
-                                let _ = self.device.set_control(control);
-                                changed = true;
-                            };
+
+                                if ui.add_enabled(can_reset, egui::Button::new("Reset").small()).clicked() {
+                                    reset_id = Some(ctrl.id);
+                                }
+
+                                if self.readback && ctrl.history.len() > 1 {
+                                    sparkline(ui, &ctrl.history);
+                                }
+                            });
                         },
 
                         v4l::control::Type::Button => {
+                            // one-shot actions such as focus or white balance triggers
+                            let response = ui.add_enabled(!disabled, egui::Button::new(ctrl.name.clone()));
+                            if response.clicked() {
+                                if let Err(er) = controls::write(&self.device, ctrl.id, v4l::control::Value::None) {
+                                    println!("Couldn't trigger {}: {}", ctrl.name, er);
+                                }
+                                changed = true;
+                            }
+                        },
+
+                        v4l::control::Type::Bitmask => {
+                            let mut mask = match ctrl.current {
+                                v4l::control::Value::Integer(val) => val,
+                                _ => 0,
+                            };
+
+                            ui.horizontal_wrapped(|ui| {
+                                ui.label(ctrl.name.clone());
+
+                                // one checkbox per bit the driver knows about
+                                for bit in (0..32).filter(|bit| ctrl.maximum & (1 << bit) != 0) {
+                                    let mut set = mask & (1 << bit) != 0;
+                                    let response = ui.add_enabled(
+                                        !disabled,
+                                        egui::Checkbox::new(&mut set, bit.to_string()));
+                                    if response.clicked() {
+                                        mask ^= 1 << bit;
+                                        ctrl.current = v4l::control::Value::Integer(mask);
+                                        let _ = controls::write(&self.device, ctrl.id, v4l::control::Value::Integer(mask));
+                                        changed = true;
+                                    }
+                                }
+
+                                if ui.add_enabled(can_reset, egui::Button::new("Reset").small()).clicked() {
+                                    reset_id = Some(ctrl.id);
+                                }
+                            });
                         },
 
-                        v4l::control::Type::Menu => {
+                        v4l::control::Type::Menu |
+                        v4l::control::Type::IntegerMenu => {
                             let mut val = 0;
                             if let v4l::control::Value::Integer(value) = ctrl.current {
                                 val = value;
@@ -363,23 +1039,29 @@ impl GuiApp {
 
                                 let name_selected = items[select_ind].1.clone();
 
-                                egui::ComboBox::from_label(ctrl.name.clone())
-                                    .selected_text(format!("{:?}", name_selected))
-                                    .show_ui(ui, |ui| {
-                                        for (val, name) in items.iter() {
-                                            let mut selected: i64 = 0;
-                                            let response = ui.selectable_value(&mut selected, *val, format!("{:?}", name));
-                                            if response.clicked() {
-                                                ctrl.current = v4l::control::Value::Integer(selected);
-                                                let control = v4l::Control {
-                                                    id: ctrl.id, 
-                                                    value: v4l::control::Value::Integer(selected),
-                                                };
-                                                let _ = self.device.set_control(control);
-                                                changed = true;
+                                ui.horizontal(|ui| {
+                                    egui::ComboBox::from_label(ctrl.name.clone())
+                                        .selected_text(format!("{:?}", name_selected))
+                                        .show_ui(ui, |ui| {
+                                            for (val, name) in items.iter() {
+                                                let mut selected: i64 = 0;
+                                                let response = ui.selectable_value(&mut selected, *val, format!("{:?}", name));
+                                                if response.clicked() {
+                                                    ctrl.current = v4l::control::Value::Integer(selected);
+                                                    let control = v4l::Control {
+                                                        id: ctrl.id, 
+                                                        value: v4l::control::Value::Integer(selected),
+                                                    };
+                                                    let _ = self.device.set_control(control);
+                                                    changed = true;
+                                                }
                                             }
-                                        }
-                                    });
+                                        });
+
+                                    if ui.add_enabled(can_reset, egui::Button::new("Reset").small()).clicked() {
+                                        reset_id = Some(ctrl.id);
+                                    }
+                                });
                             };
                               // .show_index(
                               //     ui,
@@ -392,168 +1074,850 @@ impl GuiApp {
                     }
                 }
 
-                if changed {
-                    self.update_controls().expect("update controls");
+                let reset: Vec<u32> = self.controls.iter()
+                    .filter(|ctrl| reset_all || reset_class == Some(control_class(ctrl.id)) || reset_id == Some(ctrl.id))
+                    .map(|ctrl| ctrl.id)
+                    .collect();
+
+                if !reset.is_empty() {
+                    self.reset_controls(&reset);
+                } else if changed {
+                    self.refresh_controls();
                 }
             })
     }
 
-    fn gui_settings(&mut self, ui: &mut egui::Ui) -> egui::scroll_area::ScrollAreaOutput<()> {
-    
+    fn load_device_report(&mut self) {
+        let path = match self.list_devices.get(self.device_ind) {
+            Some(info) => info.path.clone(),
+            None => format!("/dev/video{}", *self.id_mtx.lock().unwrap()),
+        };
+
+        self.device_report = Some(DeviceReport::collect(&self.device, &path)
+            .map_err(|er| format!("Couldn't query the device: {}", er)));
+    }
+
+    /// Writes the device report next to the snapshots.
+    fn export_device_report(&self, report: &DeviceReport, extension: &str) -> Result<String, String> {
+        let dir = self.snapshot_mtx.lock().unwrap().dir.clone();
+        let path = dir.join(snapshot::timestamped_name("device-info", extension));
+
+        let text = match extension {
+            "json" => report.to_json(),
+            _ => report.to_markdown(),
+        };
+
+        fs::create_dir_all(&dir)
+            .and_then(|_| fs::write(&path, text))
+            .map_err(|er| format!("{}: {}", path.display(), er))?;
+
+        Ok(format!("Saved {}", path.display()))
+    }
+
+    /// Capabilities, the format in use and every format the device offers.
+    fn gui_device_info(&mut self, ui: &mut egui::Ui) -> egui::scroll_area::ScrollAreaOutput<()> {
+        if self.device_report.is_none() {
+            self.load_device_report();
+        }
+
         egui::ScrollArea::vertical()
             .max_height(
                 ui.available_height() - ui.text_style_height(&egui::TextStyle::Body) * 2.0,
             )
             .show(ui, |ui| {
 
-                ui.spacing_mut().slider_width = 300.;
-
                 ui.set_width(ui.available_width());
 
                 ui.separator();
 
-                //let fmt = self.device.format().expect("Failed to get device format");
-                
-                egui::ComboBox::from_label("Frame Format")
-                    .selected_text(format!("{} ({})", 
-                            std::str::from_utf8(&self.list_fourcc[self.fourcc_ind].0).unwrap(), 
-                            self.list_fourcc[self.fourcc_ind].1))
-                    .show_ui(ui, |ui| {
-    
-                        for (ind, format) in self.list_fourcc[..].iter().enumerate() {
-
-                            let response = ui.selectable_value(
-                                &mut self.fourcc_ind, 
-                                ind, 
-                                format!("{} ({})", std::str::from_utf8(&format.0).unwrap(), format.1));
-                                                             
-                            if response.clicked() {
+                let report = match self.device_report.clone() {
+                    Some(Ok(report)) => report,
+                    Some(Err(er)) => {
+                        ui.colored_label(ui.visuals().error_fg_color, er);
+                        if ui.button("Refresh").clicked() {
+                            self.load_device_report();
+                        }
+                        return;
+                    },
+                    None => return,
+                };
 
-                                let fmt = self.device.format().expect("Failed to get device format");
+                ui.horizontal(|ui| {
+                    if ui.button("Refresh").clicked() {
+                        self.load_device_report();
+                    }
+                    if ui.button("Export JSON").clicked() {
+                        self.device_report_last = Some(self.export_device_report(&report, "json"));
+                    }
+                    if ui.button("Export Markdown").clicked() {
+                        self.device_report_last = Some(self.export_device_report(&report, "md"));
+                    }
+                    if ui.button("Copy Markdown").clicked() {
+                        ui.output_mut(|output| output.copied_text = report.to_markdown());
+                        self.device_report_last = Some(Ok(String::from("Copied to the clipboard")));
+                    }
 
-                                let fcc = format.0;
-                                //update frame sizes and frame rates
-                                let fourcc = v4l::FourCC::new(&fcc);
-                                                                
-                                self.list_framesize.clear();
-                                self.list_frate.clear();
+                    match &self.device_report_last {
+                        Some(Ok(message)) => { ui.label(message); },
+                        Some(Err(er)) => { ui.colored_label(ui.visuals().error_fg_color, er); },
+                        None => (),
+                    }
+                });
 
-                                self.framesize_ind = 0;
-                                self.frate_ind = 0;
+                ui.separator();
 
-                                for framesize in self.device.enum_framesizes(fourcc)
-                                    .expect("Failed to get device frame sizes") {
-                        
-                                    for discrete in framesize.size.to_discrete() {
-                            
-                                        self.list_framesize.push((discrete.width, discrete.height));
-                            
-                                        if fmt.width == discrete.width && fmt.height == discrete.height {
-                                            self.framesize_ind = self.list_framesize.len() - 1;
-                               
-                                            for frameinterval in
-                                                self.device.enum_frameintervals( 
-                                                    framesize.fourcc, discrete.width, discrete.height)
-                                                        .expect("Failed to list device frame rates") {
-                                   
-                                                match frameinterval.interval {
-                                                    FrameIntervalEnum::Discrete(fraction) => {
-                                                        self.list_frate.push((fraction.numerator, fraction.denominator));
-                                                    },
-                                                    FrameIntervalEnum::Stepwise(_stepwise) => {
-                                                        println!("Stepwise Frame Rates not supported");
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
+                egui::Grid::new("device_caps").num_columns(2).striped(true).show(ui, |ui| {
+                    let current = &report.current;
+                    let params = &report.parameters;
+
+                    let rows = [
+                        ("Device", report.path.clone()),
+                        ("Card", report.card.clone()),
+                        ("Driver", format!("{} {}", report.driver, report.version)),
+                        ("Bus", report.bus.clone()),
+                        ("Capabilities", report.capabilities.join(", ")),
+                        ("Format", format!("{} {}x{}", current.fourcc, current.width, current.height)),
+                        ("Field", current.field.clone()),
+                        ("Stride", format!("{} bytes", current.stride)),
+                        ("Image Size", format!("{} bytes", current.size_image)),
+                        ("Colorspace", current.colorspace.clone()),
+                        ("Quantization", current.quantization.clone()),
+                        ("Transfer", current.transfer.clone()),
+                        ("Frame Interval", info::interval_label(params.interval)),
+                        ("Stream Capabilities", params.capabilities.join(", ")),
+                        ("Stream Modes", params.modes.join(", ")),
+                    ];
+
+                    for (name, value) in rows {
+                        ui.label(name);
+                        ui.label(value);
+                        ui.end_row();
+                    }
+                });
+
+                ui.separator();
+                ui.heading("Formats");
+
+                for format in &report.formats {
+                    let title = if format.supported {
+                        format!("{} - {}", format.fourcc, format.description)
+                    } else {
+                        format!("{} - {} (not supported)", format.fourcc, format.description)
+                    };
+
+                    egui::CollapsingHeader::new(title)
+                        .id_source(&format.fourcc)
+                        .show(ui, |ui| {
+                            egui::Grid::new(("format_sizes", &format.fourcc)).num_columns(2).striped(true).show(ui, |ui| {
+                                for size in &format.sizes {
+                                    ui.label(size.label());
+                                    ui.label(size.intervals_label());
+                                    ui.end_row();
                                 }
+                            });
+                        });
+                }
+            })
+    }
 
-                                // update video capture
-                                let mut fcc_m = self.fourcc_mtx.lock().unwrap();
-                                *fcc_m = fcc;
-                                
-                                let mut frate = self.frate_mtx.lock().unwrap();
-                                *frate = (self.list_frate[self.frate_ind].0, 
-                                    self.list_frate[self.frate_ind].1);
+    /// Keeps the shared GUI state up to date, for saving on exit. The zoom
+    /// factor also follows Ctrl +/-.
+    fn store_state(&self, ctx: &egui::Context) {
+        let zoom = ctx.zoom_factor();
+
+        // viewport rects are in zoomed points, windows open in logical pixels
+        let geometry = ctx.input(|input| {
+            let viewport = input.viewport();
+            Some(WindowGeometry {
+                position: viewport.outer_rect.map(|rect| (rect.min.x * zoom, rect.min.y * zoom)),
+                size: viewport.inner_rect.map(|rect| (rect.width() * zoom, rect.height() * zoom))?,
+            })
+        });
+
+        let mut state = self.state_mtx.lock().unwrap();
+        state.theme = format!("{:?}", self.theme);
+        state.tab = self.tab;
+        state.ui_scale = zoom;
+        state.preview_width = self.preview_width;
+        // minimized windows have no geometry, keep the last one
+        if geometry.is_some() {
+            state.gui_window = geometry;
+        }
+    }
+
+    /// Puts the controls in `ids` back to their driver defaults. Mode controls
+    /// go first, so e.g. exposure is reset after auto exposure is.
+    fn reset_controls(&mut self, ids: &[u32]) {
+        let defaults: Vec<ProfileControl> = self.controls.iter()
+            .filter(|ctrl| ids.contains(&ctrl.id) && ctrl.resettable())
+            .map(|ctrl| ProfileControl {
+                id: ctrl.id,
+                name: ctrl.name.clone(),
+                value: match ctrl.typ {
+                    v4l::control::Type::Boolean => ProfileValue::Boolean(ctrl.default != 0),
+                    _ => ProfileValue::Integer(ctrl.default),
+                },
+            })
+            .collect();
+
+        for rejection in profile::write_controls(&self.device, &defaults) {
+            println!("Couldn't reset {}", rejection);
+        }
 
-                                let mut fsize = self.framesize_mtx.lock().unwrap();
-                                *fsize = (self.list_framesize[self.framesize_ind].0, 
-                                    self.list_framesize[self.framesize_ind].1);
+        self.refresh_controls();
+    }
+
+    fn gui_status(&mut self, ctx: &egui::Context) {
+        let status = self.status_mtx.lock().unwrap().clone();
+
+        if status.reconnects != self.reconnects {
+            // the camera came back, possibly under another /dev/videoN
+            self.reconnects = status.reconnects;
+            let id = *self.id_mtx.lock().unwrap();
+            self.list_devices = devices::enum_capture_devices();
+            self.device_ind = self.list_devices.iter().position(|d| d.index == id).unwrap_or(0);
+            self.switch_device(id);
+        }
+
+        if status.error.is_none() && status.warning.is_none() && status.corrupt_frames == 0
+            && !status.reconnecting {
+            return;
+        }
+
+        egui::TopBottomPanel::top("capture_status").show(ctx, |ui| {
+            if status.reconnecting {
+                ui.colored_label(ui.visuals().warn_fg_color,
+                    "Device disconnected, waiting for it to come back...");
+            } else if let Some(er) = &status.error {
+                ui.colored_label(ui.visuals().error_fg_color, format!("Capture stopped: {}", er));
+            } else if let Some(warning) = &status.warning {
+                ui.colored_label(ui.visuals().warn_fg_color, warning);
+            }
+
+            if status.corrupt_frames > 0 {
+                ui.label(format!("{} corrupt frames skipped", status.corrupt_frames));
+            }
+        });
+    }
+
+    fn gui_settings(&mut self, ui: &mut egui::Ui) -> egui::scroll_area::ScrollAreaOutput<()> {
+    
+        egui::ScrollArea::vertical()
+            .max_height(
+                ui.available_height() - ui.text_style_height(&egui::TextStyle::Body) * 2.0,
+            )
+            .show(ui, |ui| {
+
+                ui.spacing_mut().slider_width = 300.;
+
+                ui.set_width(ui.available_width());
 
+                ui.separator();
+
+                let mut switch_to = None;
+
+                ui.horizontal(|ui| {
+                    let selected_text = match self.list_devices.get(self.device_ind) {
+                        Some(info) => info.label(),
+                        None => String::from("no capture device found"),
+                    };
+
+                    egui::ComboBox::from_label("Device")
+                        .selected_text(selected_text)
+                        .show_ui(ui, |ui| {
+                            for (ind, info) in self.list_devices.iter().enumerate() {
+                                let response = ui.selectable_value(&mut self.device_ind, ind, info.label());
+                                if response.clicked() {
+                                    switch_to = Some(info.index);
+                                }
                             }
-                        }
-                    });
+                        });
 
-                egui::ComboBox::from_label("Frame Size")
-                    .selected_text(format!("{}x{}", 
-                            self.list_framesize[self.framesize_ind].0, 
-                            self.list_framesize[self.framesize_ind].1))
+                    if ui.button("Refresh").clicked() {
+                        let id = *self.id_mtx.lock().unwrap();
+                        self.list_devices = devices::enum_capture_devices();
+                        self.device_ind = self.list_devices.iter().position(|d| d.index == id).unwrap_or(0);
+                    }
+                });
+
+                if let Some(index) = switch_to {
+                    if index != *self.id_mtx.lock().unwrap() {
+                        self.switch_device(index);
+                    }
+                }
+
+                ui.separator();
+
+                //let fmt = self.device.format().expect("Failed to get device format");
+
+                let selected_text = match self.list_fourcc.get(self.fourcc_ind) {
+                    Some(format) => format!("{} ({})", String::from_utf8_lossy(&format.0), format.1),
+                    None => String::new(),
+                };
+
+                let mut fourcc_changed = false;
+
+                egui::ComboBox::from_label("Frame Format")
+                    .selected_text(selected_text)
                     .show_ui(ui, |ui| {
-                        
-                        for (ind, framesize) in self.list_framesize[..].iter().enumerate() {
-                            
-                            let response = ui.selectable_value(
-                                &mut self.framesize_ind,
-                                ind,
-                                format!("{}x{}", framesize.0, framesize.1));
+    
+                        for (ind, format) in self.list_fourcc[..].iter().enumerate() {
 
+                            // formats the capture pipeline can't show are listed but greyed out
+                            let supported = pixfmt::is_supported(&format.0);
+
+                            let response = ui.add_enabled_ui(supported, |ui| {
+                                ui.selectable_value(
+                                    &mut self.fourcc_ind, 
+                                    ind, 
+                                    format!("{} ({})", String::from_utf8_lossy(&format.0), format.1))
+                            }).inner.on_disabled_hover_text("Pixel format not supported");
+                                                             
                             if response.clicked() {
-                                //update frame rates
-                                self.list_frate.clear();
-                                self.frate_ind = 0;
-                               
-                                let fourcc = v4l::FourCC::new(&self.list_fourcc[self.fourcc_ind].0); 
-                                for frameinterval in
-                                    self.device.enum_frameintervals( 
-                                        fourcc, framesize.0, framesize.1)
-                                        .expect("Failed to list device frame rates") {
-                                   
-                                            match frameinterval.interval {
-                                                FrameIntervalEnum::Discrete(fraction) => {
-                                                    self.list_frate.push((fraction.numerator, fraction.denominator));
-                                                },
-                                                FrameIntervalEnum::Stepwise(_stepwise) => {
-                                                    println!("Stepwise Frame Rates not supported");
-                                                }
-                                            }
-                                }
+                                fourcc_changed = true;
+                            }
+                        }
+                    });
 
-                                //update video capture
-                                let mut frate = self.frate_mtx.lock().unwrap();
-                                *frate = (self.list_frate[self.frate_ind].0, 
-                                    self.list_frate[self.frate_ind].1);
+                if fourcc_changed {
+                    if let Some(fcc) = self.current_fourcc() {
+                        //update frame sizes and frame rates
+                        let size = *self.framesize_mtx.lock().unwrap();
+                        let frate = *self.frate_mtx.lock().unwrap();
 
-                                let mut fsize = self.framesize_mtx.lock().unwrap();
-                                *fsize = (framesize.0, framesize.1);
-                            }                                       
+                        self.load_framesizes(fcc, size);
+                        if let Some(size) = self.current_framesize() {
+                            self.load_frates(fcc, size, frate);
                         }
-                    });
 
-                egui::ComboBox::from_label("Frame Rate")
-                    .selected_text(format!("{}/{}", 
-                            self.list_frate[self.frate_ind].0, 
-                            self.list_frate[self.frate_ind].1))
-                    .show_ui(ui, |ui| {
-                        
-                        for (ind, frate) in self.list_frate[..].iter().enumerate() {
-                            
-                            let response = ui.selectable_value(
-                                &mut self.frate_ind,
-                                ind,
-                                format!("{}/{}", frate.0, frate.1));
+                        // update video capture
+                        self.publish_settings();
+                    }
+                }
 
-                            if response.clicked() {
+                let mut size_changed = false;
+
+                match self.framesize_range {
+                    Some(range) => {
+                        ui.horizontal(|ui| {
+                            ui.add(egui::DragValue::new(&mut self.range_size.0)
+                                .range(range.min.0..=range.max.0)
+                                .speed(range.step.0));
+                            ui.label("x");
+                            ui.add(egui::DragValue::new(&mut self.range_size.1)
+                                .range(range.min.1..=range.max.1)
+                                .speed(range.step.1));
+                            ui.label("Frame Size");
+
+                            if ui.button("Apply").clicked() {
+                                self.range_size = range.snap(self.range_size.0, self.range_size.1);
+                                size_changed = true;
+                            }
+                        });
+
+                        ui.weak(format!("{}x{} to {}x{}, step {}x{}",
+                            range.min.0, range.min.1, range.max.0, range.max.1, range.step.0, range.step.1));
+                    },
+                    None => {
+                        let selected_text = match self.list_framesize.get(self.framesize_ind) {
+                            Some(size) => format!("{}x{}", size.0, size.1),
+                            None => String::new(),
+                        };
+
+                        egui::ComboBox::from_label("Frame Size")
+                            .selected_text(selected_text)
+                            .show_ui(ui, |ui| {
+                                
+                                for (ind, framesize) in self.list_framesize[..].iter().enumerate() {
+                                    
+                                    let response = ui.selectable_value(
+                                        &mut self.framesize_ind,
+                                        ind,
+                                        format!("{}x{}", framesize.0, framesize.1));
+
+                                    if response.clicked() {
+                                        size_changed = true;
+                                    }
+                                }
+                            });
+                    }
+                }
+
+                if size_changed {
+                    if let (Some(fcc), Some(size)) = (self.current_fourcc(), self.current_framesize()) {
+                        //update frame rates
+                        let frate = *self.frate_mtx.lock().unwrap();
+                        self.load_frates(fcc, size, frate);
+
+                        //update video capture
+                        self.publish_settings();
+                    }
+                }
+
+                match self.frate_range {
+                    Some(range) => {
+                        let (min_fps, max_fps) = range.fps_bounds();
+
+                        ui.horizontal(|ui| {
+                            ui.add(egui::DragValue::new(&mut self.range_fps)
+                                .range(min_fps..=max_fps)
+                                .speed(0.1)
+                                .max_decimals(3)
+                                .suffix(" fps"));
+
+                            let frate = range.snap_fps(self.range_fps);
+                            ui.label(format!("= {}/{} s", frate.0, frate.1));
+                            ui.label("Frame Rate");
+
+                            if ui.button("Apply").clicked() {
                                 //update frame rate
+                                self.range_fps = 1.0 / stepwise::seconds(frate);
                                 let mut frame_rate = self.frate_mtx.lock().unwrap();
-                                *frame_rate = (frate.0, frate.1);
-                            }                                       
-                        }
-                    });
+                                *frame_rate = frate;
+                            }
+                        });
+
+                        ui.weak(format!("{:.3} to {:.3} fps", min_fps, max_fps));
+                    },
+                    None => {
+                        let selected_text = match self.list_frate.get(self.frate_ind) {
+                            Some(frate) => format!("{}/{}", frate.0, frate.1),
+                            None => String::new(),
+                        };
+
+                        egui::ComboBox::from_label("Frame Rate")
+                            .selected_text(selected_text)
+                            .show_ui(ui, |ui| {
+                                
+                                for (ind, frate) in self.list_frate[..].iter().enumerate() {
+                                    
+                                    let response = ui.selectable_value(
+                                        &mut self.frate_ind,
+                                        ind,
+                                        format!("{}/{}", frate.0, frate.1));
+
+                                    if response.clicked() {
+                                        //update frame rate
+                                        let mut frame_rate = self.frate_mtx.lock().unwrap();
+                                        *frame_rate = (frate.0, frate.1);
+                                    }                                       
+                                }
+                            });
+                    }
+                }
+
+                ui.separator();
+                self.gui_profiles(ui);
+
+                if let Some((_, packing)) = self.current_fourcc().and_then(|fcc| bayer::bayer_format(&fcc)) {
+                    ui.separator();
+                    self.gui_debayer(ui, packing.max());
+                }
+
+                ui.separator();
+                self.gui_snapshot(ui);
+
+                ui.separator();
+                self.gui_recording(ui);
+
+                ui.separator();
+                self.gui_preroll(ui);
+
+                ui.separator();
+                self.gui_motion(ui);
+
+                if let Some(addr) = self.stream_hub.addr() {
+                    ui.separator();
+                    ui.label(format!("Streaming on http://{}/stream - {} client(s)", addr, self.stream_hub.clients()));
+                }
                 
             })
     }
 
+    /// Snapshot directory and format, and a button to take one.
+    fn gui_snapshot(&mut self, ui: &mut egui::Ui) {
+        let mut snapshot = self.snapshot_mtx.lock().unwrap();
+
+        ui.horizontal(|ui| {
+            let response = ui.text_edit_singleline(&mut self.snapshot_dir);
+            if response.changed() {
+                snapshot.dir = PathBuf::from(&self.snapshot_dir);
+            }
+            ui.label("Snapshot Directory");
+        });
+
+        egui::ComboBox::from_label("Snapshot Format")
+            .selected_text(snapshot.format.label())
+            .show_ui(ui, |ui| {
+                for format in ImageFormat::ALL {
+                    ui.selectable_value(&mut snapshot.format, format, format.label());
+                }
+            });
+
+        ui.horizontal(|ui| {
+            if ui.button("Take Snapshot").clicked() {
+                snapshot.request();
+            }
+
+            match &snapshot.last {
+                Some(Ok(path)) => { ui.label(format!("Saved {}", path.display())); },
+                Some(Err(er)) => { ui.colored_label(ui.visuals().error_fg_color, er); },
+                None => (),
+            }
+        });
+    }
+
+    /// Recording directory, file split size and start/stop.
+    fn gui_recording(&mut self, ui: &mut egui::Ui) {
+        let mut recording = self.recording_mtx.lock().unwrap();
+
+        ui.horizontal(|ui| {
+            let response = ui.text_edit_singleline(&mut self.record_dir);
+            if response.changed() {
+                recording.dir = PathBuf::from(&self.record_dir);
+            }
+            ui.label("Recording Directory");
+        });
+
+        ui.horizontal(|ui| {
+            let mut split_mb = recording.split_size / 1_000_000;
+            let response = ui.add(egui::DragValue::new(&mut split_mb)
+                .range(1..=avi::MAX_SPLIT_MB)
+                .suffix(" MB"));
+            ui.label("Split AVI Files At");
+            if response.changed() {
+                recording.split_size = split_mb * 1_000_000;
+            }
+        });
+
+        ui.checkbox(&mut recording.y4m_420, "Record 4:2:2 streams as 4:2:0 Y4M");
+
+        ui.horizontal(|ui| {
+            let text = if recording.active { "Stop Recording" } else { "Start Recording" };
+            if ui.button(text).clicked() {
+                recording.toggle();
+            }
+
+            if let Some(file) = &recording.file {
+                ui.label(format!("{} - {} frames, {:.1} MB",
+                    file.display(), recording.frames, recording.bytes as f64 / 1e6));
+            }
+
+            if let Some(er) = &recording.error {
+                ui.colored_label(ui.visuals().error_fg_color, er);
+            }
+        });
+    }
+
+    /// Saved control profiles of the current device.
+    fn gui_profiles(&mut self, ui: &mut egui::Ui) {
+        let selected = self.profile_names.get(self.profile_ind).cloned();
+
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("Profile")
+                .selected_text(selected.clone().unwrap_or_default())
+                .show_ui(ui, |ui| {
+                    for (ind, name) in self.profile_names.iter().enumerate() {
+                        ui.selectable_value(&mut self.profile_ind, ind, name);
+                    }
+                });
+
+            if let Some(name) = &selected {
+                if ui.button("Load").clicked() {
+                    self.profile_last = Some(self.apply_profile(name));
+                }
+                if ui.button("Delete").clicked() {
+                    self.profile_rejected.clear();
+                    self.profile_last = Some(self.delete_profile(name));
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.profile_name);
+
+            let name = self.profile_name.trim().to_string();
+            if ui.add_enabled(!name.is_empty(), egui::Button::new("Save Profile")).clicked() {
+                self.profile_rejected.clear();
+                self.profile_last = Some(self.save_profile(&name));
+            }
+        });
+
+        match &self.profile_last {
+            Some(Ok(message)) => { ui.label(message); },
+            Some(Err(er)) => { ui.colored_label(ui.visuals().error_fg_color, er); },
+            None => (),
+        }
+
+        for rejection in &self.profile_rejected {
+            ui.colored_label(ui.visuals().warn_fg_color, format!("Rejected {}", rejection));
+        }
+    }
+
+    /// Pre-roll buffer size and the "save last N seconds" action.
+    fn gui_preroll(&mut self, ui: &mut egui::Ui) {
+        let mut preroll = self.preroll_mtx.lock().unwrap();
+
+        ui.checkbox(&mut preroll.enabled, "Keep Recent Frames In Memory");
+
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut preroll.seconds)
+                .range(1.0..=600.0)
+                .suffix(" s"));
+            ui.label("or at most");
+
+            let mut max_mb = preroll.max_bytes / 1_000_000;
+            if ui.add(egui::DragValue::new(&mut max_mb).range(1..=16000).suffix(" MB")).changed() {
+                preroll.max_bytes = max_mb * 1_000_000;
+            }
+            ui.label("Pre-roll");
+        });
+
+        if !preroll.enabled {
+            return;
+        }
+
+        ui.label(format!("{} frames, {:.1} MB buffered", preroll.frames, preroll.bytes as f64 / 1e6));
+
+        ui.horizontal(|ui| {
+            let max = preroll.seconds;
+            ui.add(egui::DragValue::new(&mut preroll.save_seconds)
+                .range(1.0..=max)
+                .prefix("Last ")
+                .suffix(" s"));
+
+            let button = ui.add_enabled(!preroll.saving, egui::Button::new("Save"));
+            if button.clicked() {
+                preroll.request_save();
+            }
+
+            match &preroll.last {
+                _ if preroll.saving => { ui.label("Saving..."); },
+                Some(Ok(path)) => { ui.label(format!("Saved {}", path.display())); },
+                Some(Err(er)) => { ui.colored_label(ui.visuals().error_fg_color, er); },
+                None => (),
+            }
+        });
+    }
+
+    /// Timelapse schedule, output and start/stop.
+    fn gui_timelapse(&mut self, ui: &mut egui::Ui) -> egui::scroll_area::ScrollAreaOutput<()> {
+
+        egui::ScrollArea::vertical()
+            .max_height(
+                ui.available_height() - ui.text_style_height(&egui::TextStyle::Body) * 2.0,
+            )
+            .show(ui, |ui| {
+
+                ui.set_width(ui.available_width());
+
+                ui.separator();
+
+                let mut timelapse = self.timelapse_mtx.lock().unwrap();
+                let running = timelapse.running;
+
+                // the schedule is fixed while running, the capture thread works from it
+                ui.add_enabled_ui(!running, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut self.timelapse_interval)
+                            .range(0.1..=10000.0)
+                            .speed(0.1));
+                        egui::ComboBox::from_id_source("timelapse_unit")
+                            .selected_text(match self.timelapse_unit { 3600 => "h", 60 => "min", _ => "s" })
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut self.timelapse_unit, 1, "s");
+                                ui.selectable_value(&mut self.timelapse_unit, 60, "min");
+                                ui.selectable_value(&mut self.timelapse_unit, 3600, "h");
+                            });
+                        ui.label("Interval");
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.add(egui::TextEdit::singleline(&mut self.timelapse_start)
+                            .hint_text("now")
+                            .desired_width(60.));
+                        ui.label("Start (HH:MM)");
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.add(egui::TextEdit::singleline(&mut self.timelapse_end)
+                            .hint_text("never")
+                            .desired_width(60.));
+                        ui.label("End (HH:MM)");
+                    });
+
+                    ui.horizontal(|ui| {
+                        let mut count = timelapse.count.unwrap_or(0);
+                        ui.add(egui::DragValue::new(&mut count).range(0..=1_000_000));
+                        ui.label("Shots (0 = no limit)");
+                        timelapse.count = (count > 0).then_some(count);
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut timelapse.output, TimelapseOutput::Images, "Images");
+                        ui.radio_value(&mut timelapse.output, TimelapseOutput::Avi, "MJPEG AVI");
+                    });
+
+                    match timelapse.output {
+                        TimelapseOutput::Images => {
+                            egui::ComboBox::from_label("Image Format")
+                                .selected_text(timelapse.image_format.label())
+                                .show_ui(ui, |ui| {
+                                    for format in ImageFormat::ALL {
+                                        ui.selectable_value(&mut timelapse.image_format, format, format.label());
+                                    }
+                                });
+                        },
+                        TimelapseOutput::Avi => {
+                            ui.horizontal(|ui| {
+                                ui.add(egui::DragValue::new(&mut timelapse.playback_fps)
+                                    .range(1..=120)
+                                    .suffix(" fps"));
+                                ui.label("Playback Rate");
+                            });
+                        },
+                    }
+
+                    ui.checkbox(&mut timelapse.stop_between, "Stop streaming between shots (10 s or longer)");
+                });
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    let text = if running { "Stop Timelapse" } else { "Start Timelapse" };
+                    if ui.button(text).clicked() {
+                        if running {
+                            timelapse.running = false;
+                        } else {
+                            match self.timelapse_schedule() {
+                                Ok((start, end)) => {
+                                    timelapse.interval = Duration::from_secs_f64(
+                                        self.timelapse_interval * self.timelapse_unit as f64);
+                                    timelapse.start = start;
+                                    timelapse.end = end;
+                                    timelapse.error = None;
+                                    timelapse.running = true;
+                                },
+                                Err(er) => timelapse.error = Some(er),
+                            }
+                        }
+                    }
+
+                    if let Some(er) = &timelapse.error {
+                        ui.colored_label(ui.visuals().error_fg_color, er);
+                    }
+                });
+
+                if let Some(path) = &timelapse.path {
+                    ui.label(format!("{} - {} shots", path.display(), timelapse.taken));
+                }
+
+                if let Some(next) = timelapse.next.filter(|_| running) {
+                    ui.label(format!("Next shot at {}", timelapse::format_clock(next)));
+                }
+            })
+    }
+
+    /// Start and end time from the text fields, empty ones meaning none.
+    fn timelapse_schedule(&self) -> Result<(Option<SystemTime>, Option<SystemTime>), String> {
+        let clock = |s: &str, what: &str| match s.trim() {
+            "" => Ok(None),
+            s => timelapse::parse_clock(s).map(Some).ok_or(format!("Invalid {} time: {}", what, s)),
+        };
+
+        Ok((clock(&self.timelapse_start, "start")?, clock(&self.timelapse_end, "end")?))
+    }
+
+    /// Motion detector thresholds, what an event saves, and the event log.
+    fn gui_motion(&mut self, ui: &mut egui::Ui) {
+        let mut motion = self.motion_mtx.lock().unwrap();
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut motion.enabled, "Detect Motion");
+            if motion.active {
+                ui.colored_label(ui.visuals().warn_fg_color, "Motion");
+            }
+        });
+
+        if !motion.enabled {
+            return;
+        }
+
+        ui.add(egui::Slider::new(&mut motion.threshold, 1..=255).text("Sensitivity Threshold"));
+        ui.add(egui::Slider::new(&mut motion.min_area, 0.01..=50.0)
+            .logarithmic(true)
+            .suffix(" %")
+            .text("Minimum Blob Area"));
+
+        ui.horizontal(|ui| {
+            match motion.roi.len() {
+                0 => ui.label("Watching the whole frame, drag in the preview to pick regions"),
+                n => ui.label(format!("Watching {} region(s)", n)),
+            };
+            if ui.add_enabled(!motion.roi.is_empty(), egui::Button::new("Clear")).clicked() {
+                motion.roi.clear();
+            }
+        });
+
+        egui::ComboBox::from_label("On Motion")
+            .selected_text(motion.action.label())
+            .show_ui(ui, |ui| {
+                for action in MotionAction::ALL {
+                    ui.selectable_value(&mut motion.action, action, action.label());
+                }
+            });
+
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut motion.pre_roll).range(0.0..=30.0).speed(0.1).suffix(" s"));
+            ui.label("Pre-roll");
+            ui.add(egui::DragValue::new(&mut motion.post_roll).range(0.0..=60.0).speed(0.1).suffix(" s"));
+            ui.label("Post-roll");
+        });
+
+        if let Some(er) = &motion.error {
+            ui.colored_label(ui.visuals().error_fg_color, er);
+        }
+
+        egui::CollapsingHeader::new(format!("Events ({})", motion.events.len()))
+            .show(ui, |ui| {
+                for event in motion.events.iter().rev() {
+                    let duration = match event.end {
+                        Some(end) => format!("{:.1} s", end.duration_since(event.start).unwrap_or_default().as_secs_f64()),
+                        None => String::from("ongoing"),
+                    };
+                    let files: Vec<String> = event.files.iter().map(|file| file.display().to_string()).collect();
+
+                    ui.label(format!("{}  {}  {:.1} %  {}",
+                        timelapse::format_clock(event.start), duration, event.peak, files.join(", ")));
+                }
+            });
+    }
+
+    /// Demosaicing, black level and white balance of raw Bayer formats.
+    fn gui_debayer(&mut self, ui: &mut egui::Ui, max: u16) {
+        let mut settings = *self.debayer_mtx.lock().unwrap();
+
+        egui::ComboBox::from_label("Demosaic")
+            .selected_text(settings.method.label())
+            .show_ui(ui, |ui| {
+                for method in [Demosaic::Bilinear, Demosaic::EdgeAware] {
+                    ui.selectable_value(&mut settings.method, method, method.label());
+                }
+            });
+
+        ui.add(egui::Slider::new(&mut settings.black_level, 0..=max / 4).text("Black Level"));
+
+        for (gain, name) in settings.gains.iter_mut().zip(["Red Gain", "Green Gain", "Blue Gain"]) {
+            ui.add(egui::Slider::new(gain, 0.0..=8.0).logarithmic(true).text(name));
+        }
+
+        if ui.button("Reset").clicked() {
+            settings = DebayerSettings::default();
+        }
+
+        *self.debayer_mtx.lock().unwrap() = settings;
+    }
+
 }
 
 impl eframe::App for GuiApp {
@@ -561,7 +1925,7 @@ impl eframe::App for GuiApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         
         if self.controls.is_empty() {
-            self.get_device_ctrls().expect("update controls");
+            let _ = self.get_device_ctrls();
         }
 
         catppuccin_egui::set_theme(
@@ -574,7 +1938,40 @@ impl eframe::App for GuiApp {
             },
         );
 
-        ctx.set_pixels_per_point(1.25);
+        self.store_state(ctx);
+
+        // the capture thread runs on its own, poll its status now and then
+        ctx.request_repaint_after(Duration::from_millis(500));
+        self.gui_status(ctx);
+
+        self.poll_control_events();
+
+        if self.readback {
+            let interval = Duration::from_secs_f64(1.0 / self.readback_hz);
+            if self.last_readback.elapsed() >= interval {
+                self.readback_controls();
+            }
+            ctx.request_repaint_after(interval);
+        }
+
+        // pick up what the HTTP API changed behind our back
+        let revisions = *self.revisions_mtx.lock().unwrap();
+        if revisions.controls != self.revisions.controls {
+            self.refresh_controls();
+        }
+        if revisions.format != self.revisions.format {
+            self.select_published();
+        }
+        self.revisions = revisions;
+
+        if let Some(preview) = &mut self.preview {
+            let panel = egui::SidePanel::right("preview")
+                .resizable(true)
+                .default_width(self.preview_width)
+                .width_range(160.0..=f32::INFINITY)
+                .show(ctx, |ui| preview.show(ui));
+            self.preview_width = panel.response.rect.width();
+        }
  
         egui::CentralPanel::default()
             .frame(egui::Frame::none())
@@ -600,6 +1997,17 @@ impl eframe::App for GuiApp {
                                 );
                                 ui.selectable_value(&mut self.theme, CatppuccinTheme::Mocha, "Mocha");
                             });
+
+                        let zoom = ctx.zoom_factor();
+                        egui::ComboBox::from_label("UI Scale:")
+                            .selected_text(format!("{:.0}%", zoom * 100.0))
+                            .show_ui(ui, |ui| {
+                                for scale in UI_SCALES {
+                                    if ui.selectable_label(zoom == scale, format!("{:.0}%", scale * 100.0)).clicked() {
+                                        ctx.set_zoom_factor(scale);
+                                    }
+                                }
+                            });
                     });
                 });
 
@@ -616,6 +2024,12 @@ impl eframe::App for GuiApp {
                         
                         let tab1 = egui::Button::new("Settings")
                             .selected(self.tab == 1);
+
+                        let tab2 = egui::Button::new("Timelapse")
+                            .selected(self.tab == 2);
+
+                        let tab3 = egui::Button::new("Device")
+                            .selected(self.tab == 3);
         
                         if ui.add_sized([120., 40.], tab0).clicked() {
                             self.tab = 0;
@@ -624,12 +2038,24 @@ impl eframe::App for GuiApp {
                         if ui.add_sized([120., 40.], tab1).clicked() {
                             self.tab = 1;
                         }
+
+                        if ui.add_sized([120., 40.], tab2).clicked() {
+                            self.tab = 2;
+                        }
+
+                        if ui.add_sized([120., 40.], tab3).clicked() {
+                            self.tab = 3;
+                        }
                     });
                     columns[1].heading("");
                 });
                 
                 if self.tab == 1 {
                     self.gui_settings(ui);
+                } else if self.tab == 2 {
+                    self.gui_timelapse(ui);
+                } else if self.tab == 3 {
+                    self.gui_device_info(ui);
                 } else {
                     self.gui_controls(ui);
                 }                  
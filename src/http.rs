@@ -0,0 +1,357 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::api;
+use crate::capture::SharedSettings;
+use crate::frame::{Frame, FrameSlot};
+use crate::snapshot;
+
+const BOUNDARY: &str = "rustycameraframe";
+
+/// How long a client may take to accept data before it's dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the last frame goes out again while no new ones come, so a
+/// client that went away is noticed by the failing write.
+const KEEPALIVE: Duration = Duration::from_secs(5);
+
+/// How long `/snapshot` waits for a frame.
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Request bodies bigger than this are refused.
+const MAX_BODY: usize = 64 * 1024;
+
+const INDEX: &str = "<!DOCTYPE html>\n<html><head><title>rustycamera</title></head>\
+<body style=\"margin:0;background:#000\"><img src=\"/stream\" style=\"width:100%\"></body></html>\n";
+
+/// Hands frames from the capture thread to the HTTP clients. Frames are only
+/// copied while someone is watching, and are JPEG encoded once on a thread of
+/// their own however many clients there are.
+pub struct StreamHub {
+    frames: FrameSlot,
+    clients: AtomicUsize,
+    latest: Mutex<(u64, Option<Arc<Vec<u8>>>)>,
+    ready: Condvar,
+    addr: Mutex<Option<SocketAddr>>,
+}
+
+impl StreamHub {
+    pub fn new() -> Self {
+        Self {
+            frames: FrameSlot::new(),
+            clients: AtomicUsize::new(0),
+            latest: Mutex::new((0, None)),
+            ready: Condvar::new(),
+            addr: Mutex::new(None),
+        }
+    }
+
+    /// Called by the capture thread with every frame, never blocks for long.
+    pub fn offer(&self, frame: &Frame) {
+        if self.clients() == 0 {
+            return;
+        }
+
+        // MJPG frames go out as sent by the camera, the decoded pixels aren't needed
+        let frame = match &frame.jpeg {
+            Some(jpeg) => Frame { data: Vec::new(), jpeg: Some(jpeg.clone()), ..*frame },
+            None => frame.clone(),
+        };
+
+        self.frames.put(frame);
+    }
+
+    /// Number of clients streaming or waiting for a snapshot.
+    pub fn clients(&self) -> usize {
+        self.clients.load(Ordering::Relaxed)
+    }
+
+    /// Where the server listens, once it does.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        *self.addr.lock().unwrap()
+    }
+
+    fn publish(&self, jpeg: Vec<u8>) {
+        let mut latest = self.latest.lock().unwrap();
+        *latest = (latest.0 + 1, Some(Arc::new(jpeg)));
+        self.ready.notify_all();
+    }
+
+    fn sequence(&self) -> u64 {
+        self.latest.lock().unwrap().0
+    }
+
+    /// Waits up to `timeout` for a frame newer than `seen`.
+    fn wait_newer(&self, seen: u64, timeout: Duration) -> Option<(u64, Arc<Vec<u8>>)> {
+        let latest = self.latest.lock().unwrap();
+        let (latest, _) = self.ready
+            .wait_timeout_while(latest, timeout, |(sequence, _)| *sequence <= seen)
+            .unwrap();
+
+        match &*latest {
+            (sequence, Some(jpeg)) if *sequence > seen => Some((*sequence, jpeg.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// Counts a client for as long as it's alive.
+struct ClientGuard<'a>(&'a StreamHub);
+
+impl<'a> ClientGuard<'a> {
+    fn new(hub: &'a StreamHub) -> Self {
+        hub.clients.fetch_add(1, Ordering::Relaxed);
+        Self(hub)
+    }
+}
+
+impl Drop for ClientGuard<'_> {
+    fn drop(&mut self) {
+        self.0.clients.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A parsed HTTP request.
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// Value of `name` in the query string.
+    fn param(&self, name: &str) -> Option<&str> {
+        self.query.split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// Binds `addr` and serves the stream and the control API from background
+/// threads. A bare port listens on all interfaces, but only clients on this
+/// machine may change controls or the format unless `api_remote` is set.
+pub fn serve(addr: &str, settings: SharedSettings, api_remote: bool) -> io::Result<SocketAddr> {
+    let hub = settings.stream.clone();
+
+    let addr = match addr.parse::<u16>() {
+        Ok(port) => format!("0.0.0.0:{}", port),
+        Err(_) => addr.to_string(),
+    };
+
+    let listener = TcpListener::bind(&addr)?;
+    let local = listener.local_addr()?;
+    *hub.addr.lock().unwrap() = Some(local);
+
+    thread::spawn(move || encode_frames(&hub));
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(er) => {
+                    println!("Failed to accept HTTP client: {}", er);
+                    continue;
+                },
+            };
+
+            let settings = settings.clone();
+            thread::spawn(move || {
+                let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+                if let Err(er) = handle(stream, &settings, api_remote) {
+                    // clients going away mid-stream is how streams usually end
+                    if !matches!(er.kind(), io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset) {
+                        println!("HTTP client {}: {}", peer, er);
+                    }
+                }
+            });
+        }
+    });
+
+    println!("serving MJPEG on http://{}/stream", local);
+
+    Ok(local)
+}
+
+fn encode_frames(hub: &StreamHub) {
+    loop {
+        let frame = match hub.frames.take_timeout(Duration::from_millis(500)) {
+            Some(frame) => frame,
+            None => continue,
+        };
+
+        match snapshot::encode_jpeg(&frame) {
+            Ok(jpeg) => hub.publish(jpeg),
+            Err(er) => println!("Failed to encode stream frame: {}", er),
+        }
+    }
+}
+
+fn handle(stream: TcpStream, settings: &SharedSettings, api_remote: bool) -> io::Result<()> {
+    let hub = &settings.stream;
+
+    stream.set_read_timeout(Some(WRITE_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let request = read_request(&mut reader)?;
+    let mut stream = stream;
+
+    if api::is_api(&request.path) {
+        let local = stream.peer_addr()?.ip().to_canonical().is_loopback();
+        let (status, reply) = api::handle(&request.method, &request.path, &request.body, settings,
+            local || api_remote);
+        return respond(&mut stream, status, "application/json", format!("{}\n", reply).as_bytes());
+    }
+
+    if request.method != "GET" {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"method not allowed\n");
+    }
+
+    match request.path.as_str() {
+        "/" => respond(&mut stream, "200 OK", "text/html", INDEX.as_bytes()),
+        "/stream" => stream_frames(&mut stream, hub, &request),
+        "/snapshot" => {
+            let _client = ClientGuard::new(hub);
+            match hub.wait_newer(hub.sequence(), SNAPSHOT_TIMEOUT) {
+                Some((_, jpeg)) => respond(&mut stream, "200 OK", "image/jpeg", &jpeg),
+                None => respond(&mut stream, "503 Service Unavailable", "text/plain", b"no frames\n"),
+            }
+        },
+        _ => respond(&mut stream, "404 Not Found", "text/plain", b"not found\n"),
+    }
+}
+
+/// Sends frames as they come. A client that can't keep up skips to the
+/// newest frame, it only ever holds up its own thread. `?fps=N` caps the
+/// rate for slow links. While capture is stalled the client is checked for
+/// having left, and the last frame is repeated now and then.
+fn stream_frames(stream: &mut TcpStream, hub: &StreamHub, request: &Request) -> io::Result<()> {
+    let _client = ClientGuard::new(hub);
+
+    let min_gap = request.param("fps")
+        .and_then(|fps| fps.parse::<f64>().ok())
+        .filter(|fps| *fps > 0.0)
+        .map(|fps| Duration::from_secs_f64(1.0 / fps))
+        .unwrap_or_default();
+
+    write!(stream, "HTTP/1.0 200 OK\r\n\
+        Content-Type: multipart/x-mixed-replace; boundary={}\r\n\
+        Cache-Control: no-cache\r\n\
+        Connection: close\r\n\r\n", BOUNDARY)?;
+
+    let peer = stream.peer_addr()?;
+    println!("stream client {} connected", peer);
+
+    let (mut seen, mut sent, mut dropped) = (hub.sequence(), 0_u64, 0_u64);
+    let mut last = Instant::now() - min_gap;
+    let mut last_jpeg: Option<Arc<Vec<u8>>> = None;
+
+    let result = loop {
+        let (sequence, jpeg) = match hub.wait_newer(seen, Duration::from_secs(1)) {
+            Some(newer) => newer,
+            None => {
+                if client_gone(stream) {
+                    break io::Error::from(io::ErrorKind::ConnectionReset);
+                }
+                if let Some(jpeg) = last_jpeg.as_ref().filter(|_| last.elapsed() >= KEEPALIVE) {
+                    if let Err(er) = write_part(stream, jpeg) {
+                        break er;
+                    }
+                    last = Instant::now();
+                }
+                continue;
+            },
+        };
+
+        if last.elapsed() < min_gap {
+            thread::sleep(min_gap - last.elapsed());
+            continue;
+        }
+
+        dropped += sequence - seen - 1;
+        seen = sequence;
+        last = Instant::now();
+
+        if let Err(er) = write_part(stream, &jpeg) {
+            break er;
+        }
+        sent += 1;
+        last_jpeg = Some(jpeg);
+    };
+
+    println!("stream client {} left after {} frames, {} skipped", peer, sent, dropped);
+
+    Err(result)
+}
+
+fn write_part(stream: &mut TcpStream, jpeg: &[u8]) -> io::Result<()> {
+    write!(stream, "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n", BOUNDARY, jpeg.len())?;
+    stream.write_all(jpeg)?;
+    stream.write_all(b"\r\n")
+}
+
+/// Whether the client closed the connection. Stream clients send nothing
+/// after their request, so anything readable is the end of it.
+fn client_gone(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return true;
+    }
+
+    let mut byte = [0; 1];
+    let gone = match stream.peek(&mut byte) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(er) => er.kind() != io::ErrorKind::WouldBlock,
+    };
+
+    stream.set_nonblocking(false).is_err() || gone
+}
+
+/// Reads the request line, headers and body, if any.
+fn read_request(reader: &mut BufReader<TcpStream>) -> io::Result<Request> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or(invalid("empty request"))?.to_string();
+    let target = parts.next().ok_or(invalid("no request target"))?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let (path, query) = (path.to_string(), query.to_string());
+
+    let mut length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().map_err(|_| invalid("bad content length"))?;
+            }
+        }
+    }
+
+    if length > MAX_BODY {
+        return Err(invalid("request too large"));
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Request { method, path, query, body })
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    write!(stream, "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status, content_type, body.len())?;
+    stream.write_all(body)?;
+    stream.flush()
+}
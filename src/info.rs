@@ -0,0 +1,248 @@
+use std::fmt::Write;
+use std::io;
+
+use serde::Serialize;
+use v4l::frameinterval::FrameIntervalEnum;
+use v4l::framesize::FrameSizeEnum;
+use v4l::video::Capture;
+use v4l::{Device, FourCC};
+
+use crate::api::fourcc_str;
+use crate::pixfmt;
+use crate::stepwise::{self, IntervalRange, SizeRange};
+
+/// What `v4l2-ctl --all` tells about a capture device: capabilities, the
+/// format in use and every format, size and interval it offers.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceReport {
+    pub path: String,
+    pub driver: String,
+    pub card: String,
+    pub bus: String,
+    pub version: String,
+    pub capabilities: Vec<String>,
+    pub current: CurrentFormat,
+    pub parameters: StreamParameters,
+    pub formats: Vec<FormatInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CurrentFormat {
+    pub fourcc: String,
+    pub width: u32,
+    pub height: u32,
+    pub field: String,
+    pub stride: u32,
+    pub size_image: u32,
+    pub colorspace: String,
+    pub quantization: String,
+    pub transfer: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamParameters {
+    pub capabilities: Vec<String>,
+    pub modes: Vec<String>,
+    pub interval: (u32, u32),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FormatInfo {
+    pub fourcc: String,
+    pub description: String,
+    /// Whether the capture pipeline can show it.
+    pub supported: bool,
+    pub sizes: Vec<SizeInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum SizeInfo {
+    Discrete { width: u32, height: u32, intervals: Vec<IntervalInfo> },
+    Stepwise { min: (u32, u32), max: (u32, u32), step: (u32, u32) },
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum IntervalInfo {
+    Discrete((u32, u32)),
+    /// `step` is `None` for continuous ranges.
+    Stepwise { min: (u32, u32), max: (u32, u32), step: Option<(u32, u32)> },
+}
+
+impl DeviceReport {
+    pub fn collect(dev: &Device, path: &str) -> io::Result<Self> {
+        let caps = dev.query_caps()?;
+        let fmt = dev.format()?;
+        let params = dev.params()?;
+
+        Ok(Self {
+            path: path.to_string(),
+            driver: caps.driver,
+            card: caps.card,
+            bus: caps.bus,
+            version: format!("{}.{}.{}", caps.version.0, caps.version.1, caps.version.2),
+            capabilities: flag_names(caps.capabilities),
+            current: CurrentFormat {
+                fourcc: fourcc_str(&fmt.fourcc.repr),
+                width: fmt.width,
+                height: fmt.height,
+                field: fmt.field_order.to_string(),
+                stride: fmt.stride,
+                size_image: fmt.size,
+                colorspace: fmt.colorspace.to_string(),
+                quantization: fmt.quantization.to_string(),
+                transfer: fmt.transfer.to_string(),
+            },
+            parameters: StreamParameters {
+                capabilities: flag_names(params.capabilities),
+                modes: flag_names(params.modes),
+                interval: (params.interval.numerator, params.interval.denominator),
+            },
+            formats: formats(dev)?,
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default() + "\n"
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+
+        // writing to a String can't fail
+        let _ = self.write_markdown(&mut md);
+
+        md
+    }
+
+    fn write_markdown(&self, md: &mut String) -> std::fmt::Result {
+        writeln!(md, "# {}\n", self.card)?;
+        writeln!(md, "| | |\n|---|---|")?;
+        writeln!(md, "| Device | {} |", self.path)?;
+        writeln!(md, "| Driver | {} {} |", self.driver, self.version)?;
+        writeln!(md, "| Bus | {} |", self.bus)?;
+        writeln!(md, "| Capabilities | {} |", self.capabilities.join(", "))?;
+
+        let current = &self.current;
+        writeln!(md, "\n## Current Format\n")?;
+        writeln!(md, "| | |\n|---|---|")?;
+        writeln!(md, "| Format | {} {}x{} |", current.fourcc, current.width, current.height)?;
+        writeln!(md, "| Field | {} |", current.field)?;
+        writeln!(md, "| Stride | {} bytes |", current.stride)?;
+        writeln!(md, "| Image Size | {} bytes |", current.size_image)?;
+        writeln!(md, "| Colorspace | {} |", current.colorspace)?;
+        writeln!(md, "| Quantization | {} |", current.quantization)?;
+        writeln!(md, "| Transfer | {} |", current.transfer)?;
+
+        let params = &self.parameters;
+        writeln!(md, "\n## Stream Parameters\n")?;
+        writeln!(md, "| | |\n|---|---|")?;
+        writeln!(md, "| Frame Interval | {} |", interval_label(params.interval))?;
+        writeln!(md, "| Capabilities | {} |", params.capabilities.join(", "))?;
+        writeln!(md, "| Modes | {} |", params.modes.join(", "))?;
+
+        writeln!(md, "\n## Formats")?;
+        for format in &self.formats {
+            let note = if format.supported { "" } else { " (not supported by rustycamera)" };
+            writeln!(md, "\n### {} - {}{}\n", format.fourcc, format.description, note)?;
+            writeln!(md, "| Size | Frame Intervals |\n|---|---|")?;
+            for size in &format.sizes {
+                writeln!(md, "| {} | {} |", size.label(), size.intervals_label())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl SizeInfo {
+    pub fn label(&self) -> String {
+        match self {
+            Self::Discrete { width, height, .. } => format!("{}x{}", width, height),
+            Self::Stepwise { min, max, step } =>
+                format!("{}x{} to {}x{}, step {}x{}", min.0, min.1, max.0, max.1, step.0, step.1),
+        }
+    }
+
+    pub fn intervals_label(&self) -> String {
+        match self {
+            Self::Discrete { intervals, .. } => intervals.iter()
+                .map(|interval| match interval {
+                    IntervalInfo::Discrete(interval) => interval_label(*interval),
+                    IntervalInfo::Stepwise { min, max, step } => match step {
+                        Some(step) => format!("{} to {}, step {}/{} s", interval_label(*min), interval_label(*max), step.0, step.1),
+                        None => format!("{} to {}", interval_label(*min), interval_label(*max)),
+                    },
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+            Self::Stepwise { .. } => String::from("-"),
+        }
+    }
+}
+
+/// "1/30 s (30.000 fps)".
+pub fn interval_label(interval: (u32, u32)) -> String {
+    if interval.0 == 0 || interval.1 == 0 {
+        return format!("{}/{} s", interval.0, interval.1);
+    }
+
+    format!("{}/{} s ({:.3} fps)", interval.0, interval.1, 1.0 / stepwise::seconds(interval))
+}
+
+/// Every format of `dev` with its frame sizes and, for discrete sizes, the
+/// frame intervals.
+pub fn formats(dev: &Device) -> io::Result<Vec<FormatInfo>> {
+    let mut formats = Vec::new();
+
+    for format in dev.enum_formats()? {
+        let mut sizes = Vec::new();
+
+        for framesize in dev.enum_framesizes(format.fourcc).unwrap_or_default() {
+            match framesize.size {
+                FrameSizeEnum::Discrete(size) => sizes.push(SizeInfo::Discrete {
+                    width: size.width,
+                    height: size.height,
+                    intervals: intervals(dev, format.fourcc, size.width, size.height),
+                }),
+                FrameSizeEnum::Stepwise(stepwise) => {
+                    let range = SizeRange::new(&stepwise, framesize.typ);
+                    sizes.push(SizeInfo::Stepwise { min: range.min, max: range.max, step: range.step });
+                },
+            }
+        }
+
+        formats.push(FormatInfo {
+            fourcc: fourcc_str(&format.fourcc.repr),
+            description: format.description,
+            supported: pixfmt::is_supported(&format.fourcc.repr),
+            sizes,
+        });
+    }
+
+    Ok(formats)
+}
+
+fn intervals(dev: &Device, fourcc: FourCC, width: u32, height: u32) -> Vec<IntervalInfo> {
+    dev.enum_frameintervals(fourcc, width, height)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|frameinterval| match frameinterval.interval {
+            FrameIntervalEnum::Discrete(fraction) => IntervalInfo::Discrete((fraction.numerator, fraction.denominator)),
+            FrameIntervalEnum::Stepwise(stepwise) => {
+                let range = IntervalRange::new(&stepwise, frameinterval.typ);
+                IntervalInfo::Stepwise { min: range.min, max: range.max, step: range.step }
+            },
+        })
+        .collect()
+}
+
+/// Names of the flags set in `flags`, from their `Debug` output ("A | B").
+fn flag_names<F: std::fmt::Debug>(flags: F) -> Vec<String> {
+    format!("{:?}", flags)
+        .split(" | ")
+        .filter(|name| !name.is_empty() && !name.starts_with('('))
+        .map(String::from)
+        .collect()
+}
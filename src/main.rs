@@ -1,183 +1,254 @@
 use std::sync::{Arc, Mutex};
-use std::sync::mpsc;
 use std::thread;
+use std::time::{Duration, Instant};
 
 use v4l::prelude::*;
 use v4l::video::Capture;
-use v4l::buffer::Type;
 use v4l::FourCC;
-use v4l::io::traits::{CaptureStream, Stream};
-
-use zune_core::colorspace::ColorSpace;
-use zune_core::options::DecoderOptions;
-use zune_jpeg::JpegDecoder;
 
+mod api;
+mod avi;
+mod bayer;
+mod capture;
+mod cli;
+mod controls;
+mod devices;
+mod frame;
 mod gui;
+mod http;
+mod info;
+mod motion;
+mod pixfmt;
+mod preroll;
+mod preview;
+mod profile;
+mod record;
 mod render;
+mod snapshot;
+mod state;
+mod stepwise;
+mod timelapse;
+mod y4m;
+
+use frame::FrameSlot;
 
-pub fn to_bytes(input: &[u32]) -> Vec<u8> {
-    let mut bytes = Vec::with_capacity(4 * input.len());
+fn main() {
+
+    let args = match cli::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(er) => {
+            eprintln!("{}\n\n{}", er, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
 
-    for value in input {
-        bytes.extend(&value.to_be_bytes());
+    if args.help {
+        println!("{}", cli::USAGE);
+        return;
     }
 
-    bytes
-}
+    let state_path = state::default_path();
+    let gui_state = state::GuiState::load(&state_path);
 
-fn main() {
+    // the last device, wherever it's plugged in now, and the format it was
+    // left at unless the command line asks for another device or format
+    let last_device = gui_state.device.as_ref().and_then(devices::find_device);
+    let id = args.device.or(last_device.as_ref().map(|device| device.index)).unwrap_or(0);
+    let last_format = gui_state.format.as_ref()
+        .filter(|_| last_device.as_ref().is_some_and(|device| device.index == id))
+        .filter(|_| args.fourcc.is_none() && args.size.is_none() && args.interval.is_none())
+        .and_then(|format| Some((cli::parse_fourcc(&format.fourcc).ok()?, format)));
 
-    let id = 0_usize;
-    //let fwidth = 640;
-    //let fheight = 480;
-    let fcc = b"YUYV";
+    let mut fcc = args.fourcc.or(last_format.map(|(fcc, _)| fcc)).unwrap_or(*b"YUYV");
+    let mut interval = args.interval.or(last_format.map(|(_, format)| format.interval)).unwrap_or((1, 30));
+    let size = args.size.or(last_format.map(|(_, format)| (format.width, format.height)));
+    let buffers = args.buffers;
+    let profile_file = args.profile_file.clone().unwrap_or_else(profile::default_path);
 
     let dev = Device::new(id).expect("Failed to open device");
     
     let mut fmt = dev.format().expect("Failed to get Device format");
-    //fmt.width = fwidth;
-    //fmt.height = fheight;
-    fmt.fourcc = FourCC::new(fcc);
+    if let Some((fwidth, fheight)) = size {
+        fmt.width = fwidth;
+        fmt.height = fheight;
+    }
 
-    
-    let id_mtx = Arc::new(Mutex::new(id));
-    let frate_mtx = Arc::new(Mutex::new((1, 30)));
-    let framesize_mtx = Arc::new(Mutex::new((fmt.width, fmt.height)));
-    let fourcc_mtx : Arc<Mutex<[u8; 4]>> = Arc::new(Mutex::new(*fcc));
+    if let Some(name) = &args.profile {
+        let profile = devices::device_info(id)
+            .ok_or(format!("device {} is not a capture device", id))
+            .and_then(|info| profile::find(&profile_file, &info, name))
+            .and_then(|profile| Ok((profile.fourcc()?, profile)));
+
+        let (profile_fcc, profile) = match profile {
+            Ok(profile) => profile,
+            Err(er) => {
+                eprintln!("Failed to load profile: {}", er);
+                std::process::exit(1);
+            }
+        };
 
-    let id_mtx_clone = id_mtx.clone();
-    let frate_mtx_clone = frate_mtx.clone();
-    let framesize_mtx_clone = framesize_mtx.clone();
-    let fourcc_mtx_clone = fourcc_mtx.clone();
+        fcc = profile_fcc;
+        (fmt.width, fmt.height) = (profile.width, profile.height);
+        interval = profile.interval;
 
-    let (tx, rx) = mpsc::channel();
+        for rejection in profile.apply_controls(&dev) {
+            println!("profile {}: control rejected: {}", name, rejection);
+        }
+    }
 
-    //v4l capture thread
-    thread::spawn(move || {
-        
-        let mut fmt = dev.set_format(&fmt).expect("Failed to write format");
-        // The actual format chosen by the device driver may differ from what we
-        // requested! Print it out to get an idea of what is actually used now.
-        println!("Format in use:\n{}", fmt);
-
-        let mut parms = dev.params().expect("Failed to load device parameters");
-
-        println!("Parameters in use:\n{:?}", parms);
- 
-        // Create the stream, which will internally 'allocate' (as in map) the
-        // number of requested buffers for us.
-        let mut stream = MmapStream::with_buffers(&dev, Type::VideoCapture, 4)
-            .unwrap();
-
-        // At this point, the stream is ready and all buffers are setup.
-        // We can now read frames (represented as buffers) by iterating through
-        // the stream. Once an error condition occurs, the iterator will return
-        // None.
-        loop {
-
-            let fcc = *fourcc_mtx.lock().unwrap();
-            let (frate_num, frate_denom) = *frate_mtx.lock().unwrap(); 
-            let (width, height) = *framesize_mtx.lock().unwrap();
-
-            if parms.interval.denominator != frate_denom || parms.interval.numerator != frate_num {
-
-                parms.interval.denominator = frate_denom;
-                parms.interval.numerator = frate_num;
-                
-                match stream.stop() {
-            
-                    Ok(_) => { 
-                        match dev.set_params(&parms) {
-                            Ok(parms) => {println!("Parameters set to:\n{}", parms);},
-                            Err(er) => {println!("Failed to set Parameters: {}", er);}
-                        }
-                        if let Err(er) = stream.start() {println!("Failed to start video stream: {}", er);}
-                    },
-                    Err(er) => {println!("Failed to stop video stream: {}", er);}
-                }
-            }
+    fmt.fourcc = FourCC::new(&fcc);
 
-            if fmt.width != width || fmt.height != height || !fmt.fourcc.repr.eq(&fcc) {
-                
-                    fmt.width = width;
-                    fmt.height = height;
-                    fmt.fourcc = v4l::FourCC::new(&fcc);
-
-                    stream.stop().expect("Couldn't stop video stream");
-                    //must drop the old stream to set the new frame format 
-                    drop(stream);
-
-                    fmt = dev.set_format(&fmt).expect("Failed to write format");
-                    // The actual format chosen by the device driver may differ from what we
-                    // requested! Print it out to get an idea of what is actually used now.
-                    println!("Format in use:\n{}", fmt);
-                    
-                    stream = MmapStream::with_buffers(&dev, Type::VideoCapture, 4)
-            .unwrap();
-
-            } 
-            
-            let (buf, _meta) = stream.next().unwrap();
-
-           // println!(
-           //     "Buffer size: {}, seq: {}, timestamp: {}",
-           //     buf.len(),
-           //     meta.sequence,
-           //     meta.timestamp
-           // );
-
-           // To process the captured data, you can pass it somewhere else.
-           // If you want to modify the data or extend its lifetime, you have to
-           // copy it. This is a best-effort tradeoff solution that allows for
-           // zero-copy readers while enforcing a full clone of the data for
-           // writers.
-
-           let data = match &fmt.fourcc.repr {
-               b"YUYV" => buf.to_vec(),
-               b"MJPG" => {
-                    let options = DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::RGBA);
-                    // Decode the JPEG frame to RGBA
-                    let mut decoder = JpegDecoder::new_with_options(buf, options);
-                    //let info = decoder.info().unwrap();
-                    //eprintln!("{:?}", info);
-                    //rgb vec
-                   
-                    decoder.decode().expect("failed to decode JPEG")
-               }
-               _ => panic!("invalid buffer pixelformat"),
-           };
-
-           tx.send(fmt.fourcc.repr.to_vec()).unwrap();
-           
-           let vsize: Vec<u32> = vec![fmt.width, fmt.height];
-           let frame_size = to_bytes(&vsize);
-
-           tx.send(frame_size).unwrap();
-
-           tx.send(data).unwrap();
+    
+    let id_mtx = Arc::new(Mutex::new(id));
+    let frate_mtx = Arc::new(Mutex::new(interval));
+    let framesize_mtx = Arc::new(Mutex::new((fmt.width, fmt.height)));
+    let fourcc_mtx : Arc<Mutex<[u8; 4]>> = Arc::new(Mutex::new(fcc));
+
+    let status_mtx = Arc::new(Mutex::new(capture::CaptureStatus::default()));
+    let debayer_mtx = Arc::new(Mutex::new(bayer::DebayerSettings::default()));
+    let mut recording = record::Recording::new(args.record_dir.clone(), args.split_size * 1_000_000);
+    recording.y4m_420 = args.y4m_420;
+    let recording_mtx = Arc::new(Mutex::new(recording));
+    let preroll_mtx = Arc::new(Mutex::new(
+        preroll::Preroll::new(args.preroll, args.preroll_mb * 1_000_000)));
+    let snapshot_mtx = Arc::new(Mutex::new(
+        snapshot::Snapshot::new(args.snapshot_dir.clone(), args.snapshot_format)));
+
+    let mut timelapse = timelapse::Timelapse::new(args.timelapse.unwrap_or(Duration::from_secs(10)));
+    timelapse.running = args.timelapse.is_some();
+    timelapse.start = args.timelapse_start;
+    timelapse.end = args.timelapse_end;
+    timelapse.count = args.timelapse_count;
+    timelapse.image_format = args.snapshot_format;
+    timelapse.stop_between = args.timelapse_stop;
+    if let Some(fps) = args.timelapse_avi {
+        timelapse.output = timelapse::TimelapseOutput::Avi;
+        timelapse.playback_fps = fps;
+    }
+    let timelapse_mtx = Arc::new(Mutex::new(timelapse));
+    let motion_mtx = Arc::new(Mutex::new(motion::Motion::new(args.motion)));
+    let quit_mtx = Arc::new(Mutex::new(false));
+    let state_mtx = Arc::new(Mutex::new(gui_state));
+
+    let settings = capture::SharedSettings {
+        id: id_mtx,
+        frate: frate_mtx,
+        framesize: framesize_mtx,
+        fourcc: fourcc_mtx,
+        status: status_mtx,
+        debayer: debayer_mtx,
+        recording: recording_mtx.clone(),
+        preroll: preroll_mtx,
+        timelapse: timelapse_mtx,
+        motion: motion_mtx.clone(),
+        snapshot: snapshot_mtx.clone(),
+        stream: Arc::new(http::StreamHub::new()),
+        revisions: Arc::new(Mutex::new(api::Revisions::default())),
+        quit: quit_mtx.clone(),
+    };
+
+    if let Some(addr) = &args.http {
+        if let Err(er) = http::serve(addr, settings.clone(), args.http_api_remote) {
+            eprintln!("Failed to start HTTP server on {}: {}", addr, er);
+            std::process::exit(1);
         }
+    }
 
-    });
+    // latest-frame handoff to the renderer, so a stalled preview window can't
+    // make frames pile up in memory
+    let frame_slot = Arc::new(FrameSlot::new());
+    let frame_slot_render = frame_slot.clone();
 
-    //render thread 
-    let _th_join_handle = thread::spawn( move|| {
+    //v4l capture thread
+    let mut capture = capture::Capture::new(dev, id, fmt, buffers, !args.no_reconnect, settings.clone(), frame_slot);
+    let capture_handle = thread::spawn(move || capture.run());
+
+    //render thread, unless the preview is part of the GUI window
+    let embedded = args.preview == preview::PreviewBackend::Embedded;
+    let preview_frames = embedded.then(|| frame_slot_render.clone());
+    let snapshot_render = snapshot_mtx.clone();
+    let state_render = state_mtx.clone();
+    let th_join_handle = (!embedded).then(|| thread::spawn( move|| {
         let mut rend = render::Render::new(
             fmt.width,
             fmt.height, 
-            &fmt.fourcc.repr);
+            &fmt.fourcc.repr,
+            snapshot_render,
+            recording_mtx,
+            motion_mtx,
+            state_render);
+
+        let _ = rend.render_data(frame_slot_render);
+    }));
+
+    if args.no_gui {
+        if let Some(handle) = th_join_handle {
+            let _ = handle.join();
+        }
+        shutdown(&quit_mtx, capture_handle);
+        save_state(&state_mtx, &settings, &state_path);
+        return;
+    }
 
-        let _ = rend.render_data(rx);
-    });
+    //gui window, where it was left last time
+    let mut native_options = eframe::NativeOptions::default();
+    match state_mtx.lock().unwrap().gui_window {
+        Some(geometry) => {
+            native_options.viewport = native_options.viewport.with_inner_size(geometry.size);
+            if let Some(position) = geometry.position {
+                native_options.viewport = native_options.viewport.with_position(position);
+            }
+        },
+        // room for the preview next to the controls
+        None if embedded => native_options.viewport = native_options.viewport.with_inner_size([1280.0, 720.0]),
+        None => (),
+    }
 
-    //gui window
-    let native_options = eframe::NativeOptions::default();
+    let gui_settings = settings.clone();
+    let gui_state = state_mtx.clone();
     let _ = eframe::run_native("rustycamera",
         native_options, 
         Box::new(|cc| {
             Ok(Box::new(
-                gui::GuiApp::new(cc, id_mtx_clone, frate_mtx_clone, framesize_mtx_clone, fourcc_mtx_clone)))
+                gui::GuiApp::new(cc, gui_settings, snapshot_mtx, profile_file, gui_state, preview_frames)))
             }
         )
     );
-    
+
+    shutdown(&quit_mtx, capture_handle);
+    save_state(&state_mtx, &settings, &state_path);
+}
+
+/// Saves the window layout along with the device and format in use, for
+/// the next start. An unplugged device keeps the one saved before.
+fn save_state(state_mtx: &Mutex<state::GuiState>, settings: &capture::SharedSettings, path: &std::path::Path) {
+    let mut state = state_mtx.lock().unwrap();
+
+    if let Some(device) = devices::device_info(*settings.id.lock().unwrap()) {
+        let fourcc = *settings.fourcc.lock().unwrap();
+        let (width, height) = *settings.framesize.lock().unwrap();
+
+        state.device = Some(device);
+        state.format = Some(state::SavedFormat {
+            fourcc: String::from_utf8_lossy(&fourcc).trim_end().to_string(),
+            width,
+            height,
+            interval: *settings.frate.lock().unwrap(),
+        });
+    }
+
+    if let Err(er) = state.save(path) {
+        println!("Failed to save GUI state: {}", er);
+    }
+}
+
+/// Asks the capture thread to stop and gives it a moment to finish open
+/// recordings, so they are readable. A hung device doesn't keep us alive.
+fn shutdown(quit: &Mutex<bool>, capture: thread::JoinHandle<()>) {
+    *quit.lock().unwrap() = true;
+
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while !capture.is_finished() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
 }
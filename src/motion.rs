@@ -0,0 +1,736 @@
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::bayer;
+use crate::frame::Frame;
+use crate::preroll::PrerollBuffer;
+use crate::record::{Recording, Writer};
+use crate::snapshot::{self, ImageFormat};
+
+/// Frames are compared on a grid about this many cells wide, which is plenty
+/// to find things moving and averages away most of the sensor noise.
+const GRID_WIDTH: usize = 160;
+
+/// Events kept for the GUI, older ones are only in the log file.
+const MAX_EVENTS: usize = 100;
+
+/// Memory the frames kept for a clip's pre-roll may take, whatever its
+/// length; the default of the pre-roll buffer.
+const MAX_HISTORY_BYTES: u64 = 500_000_000;
+
+/// Frames waiting for the clip writer. Past this the writer is falling
+/// behind and frames are dropped rather than holding up capture.
+const CLIP_QUEUE: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotionAction {
+    Snapshot,
+    Clip,
+    Both,
+}
+
+impl MotionAction {
+    pub const ALL: [MotionAction; 3] = [MotionAction::Snapshot, MotionAction::Clip, MotionAction::Both];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            MotionAction::Snapshot => "Snapshot",
+            MotionAction::Clip => "Clip",
+            MotionAction::Both => "Snapshot and Clip",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "snapshot" => Some(MotionAction::Snapshot),
+            "clip" => Some(MotionAction::Clip),
+            "both" => Some(MotionAction::Both),
+            _ => None,
+        }
+    }
+
+    fn snapshot(&self) -> bool {
+        *self != MotionAction::Clip
+    }
+
+    fn clip(&self) -> bool {
+        *self != MotionAction::Snapshot
+    }
+}
+
+/// A rectangle in frame coordinates scaled to 0..1, so it survives frame
+/// size changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Roi {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Roi {
+    /// The rectangle spanned by two corners, in any order.
+    pub fn from_corners(a: (f32, f32), b: (f32, f32)) -> Self {
+        Self {
+            x: a.0.min(b.0),
+            y: a.1.min(b.1),
+            width: (a.0 - b.0).abs(),
+            height: (a.1 - b.1).abs(),
+        }
+    }
+
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+/// Bounding box of a changed area, in frame pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Blob {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Changed area in percent of the frame.
+    pub area: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct MotionEvent {
+    pub start: SystemTime,
+    /// `None` while the event is going on.
+    pub end: Option<SystemTime>,
+    /// Largest blob seen, in percent of the frame.
+    pub peak: f32,
+    pub files: Vec<PathBuf>,
+}
+
+/// Detector settings and status, shared by the GUI, the preview window and
+/// the capture thread. Snapshots, clips and the event log go to the
+/// recording directory.
+pub struct Motion {
+    pub enabled: bool,
+    /// Luma difference (0-255) for a grid cell to count as changed.
+    pub threshold: u8,
+    /// Smallest blob that counts as motion, in percent of the frame.
+    pub min_area: f32,
+    /// Areas to watch, all of the frame when empty.
+    pub roi: Vec<Roi>,
+    pub action: MotionAction,
+    /// Seconds of video kept from before the motion started.
+    pub pre_roll: f64,
+    /// Seconds without motion before an event ends.
+    pub post_roll: f64,
+    /// What moved in the latest frame.
+    pub blobs: Vec<Blob>,
+    pub active: bool,
+    /// Newest last.
+    pub events: VecDeque<MotionEvent>,
+    pub error: Option<String>,
+}
+
+impl Motion {
+    pub fn new(action: Option<MotionAction>) -> Self {
+        Self {
+            enabled: action.is_some(),
+            threshold: 20,
+            min_area: 0.5,
+            roi: Vec::new(),
+            action: action.unwrap_or(MotionAction::Snapshot),
+            pre_roll: 2.0,
+            post_roll: 3.0,
+            blobs: Vec::new(),
+            active: false,
+            events: VecDeque::new(),
+            error: None,
+        }
+    }
+}
+
+/// A downscaled luma plane, one byte per grid cell.
+#[derive(Debug, Clone)]
+pub struct LumaPlane {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+/// Where the luma of a pixel is found in a row of a decoded frame.
+#[derive(Clone, Copy)]
+enum Layout {
+    /// A luma byte `offset` into every `step` bytes.
+    Luma { offset: usize, step: usize },
+    /// RGB or BGR pixels `step` bytes apart, the luma is approximated.
+    Rgb { step: usize },
+}
+
+impl Layout {
+    fn for_frame(frame: &Frame) -> Option<Layout> {
+        if bayer::is_bayer(&frame.fourcc) {
+            return Some(Layout::Rgb { step: 3 });
+        }
+
+        match &frame.fourcc {
+            b"YUYV" | b"YVYU" => Some(Layout::Luma { offset: 0, step: 2 }),
+            b"UYVY" => Some(Layout::Luma { offset: 1, step: 2 }),
+            b"NV12" | b"NV21" | b"YU12" | b"YV12" | b"GREY" => Some(Layout::Luma { offset: 0, step: 1 }),
+            // the high byte of the little endian samples
+            b"Y16 " => Some(Layout::Luma { offset: 1, step: 2 }),
+            b"RGB3" | b"BGR3" => Some(Layout::Rgb { step: 3 }),
+            b"MJPG" => Some(Layout::Rgb { step: 4 }),
+            _ => None,
+        }
+    }
+
+    fn luma(&self, row: &[u8], x: usize) -> u32 {
+        match *self {
+            Layout::Luma { offset, step } => row[x * step + offset] as u32,
+            Layout::Rgb { step } => {
+                let pixel = &row[x * step..x * step + 3];
+                (pixel[0] as u32 + 2 * pixel[1] as u32 + pixel[2] as u32) / 4
+            },
+        }
+    }
+}
+
+/// Averages `scale` x `scale` pixel blocks of the frame's luma, leftover
+/// pixels at the right and bottom edges are ignored.
+pub fn luma_plane(frame: &Frame, scale: usize) -> Option<LumaPlane> {
+    let layout = Layout::for_frame(frame)?;
+    let stride = frame.stride as usize;
+    let (width, height) = (frame.width as usize / scale, frame.height as usize / scale);
+
+    if width == 0 || height == 0 || frame.data.len() < stride * height * scale {
+        return None;
+    }
+
+    let mut sums = vec![0_u32; width];
+    let mut data = Vec::with_capacity(width * height);
+
+    for block in frame.data.chunks(stride * scale).take(height) {
+        sums.fill(0);
+
+        for row in block.chunks(stride) {
+            for (cell, sum) in sums.iter_mut().enumerate() {
+                *sum += (cell * scale..(cell + 1) * scale).map(|x| layout.luma(row, x)).sum::<u32>();
+            }
+        }
+
+        data.extend(sums.iter().map(|sum| (sum / (scale * scale) as u32) as u8));
+    }
+
+    Some(LumaPlane { width, height, data })
+}
+
+/// Frame differencing on downscaled luma. Doesn't touch any shared state, so
+/// it can be fed any sequence of frames.
+pub struct MotionDetector {
+    previous: Option<LumaPlane>,
+    /// Size of the frame `previous` was made from. Frames of different
+    /// sizes may still give planes of the same size.
+    frame_size: (u32, u32),
+}
+
+impl MotionDetector {
+    pub fn new() -> Self {
+        Self {
+            previous: None,
+            frame_size: (0, 0),
+        }
+    }
+
+    /// Compares `frame` with the one before and returns the changed areas
+    /// of at least `min_area` percent inside `roi`. The first frame, and the
+    /// first after a size change, never shows motion.
+    pub fn detect(&mut self, frame: &Frame, threshold: u8, min_area: f32, roi: &[Roi]) -> Vec<Blob> {
+        let scale = (frame.width as usize / GRID_WIDTH).max(1);
+
+        if (frame.width, frame.height) != self.frame_size {
+            self.frame_size = (frame.width, frame.height);
+            self.previous = None;
+        }
+
+        let previous = match luma_plane(frame, scale) {
+            Some(plane) => self.previous.replace(plane),
+            None => {
+                self.previous = None;
+                return Vec::new();
+            },
+        };
+
+        let current = self.previous.as_ref().unwrap();
+        let previous = match previous {
+            Some(previous) if (previous.width, previous.height) == (current.width, current.height) => previous,
+            _ => return Vec::new(),
+        };
+
+        let (width, height) = (current.width, current.height);
+
+        let mut changed: Vec<bool> = current.data.iter()
+            .zip(&previous.data)
+            .enumerate()
+            .map(|(i, (&a, &b))| {
+                let (x, y) = ((i % width) as f32 + 0.5, (i / width) as f32 + 0.5);
+                let watched = roi.is_empty()
+                    || roi.iter().any(|roi| roi.contains(x / width as f32, y / height as f32));
+                watched && a.abs_diff(b) > threshold
+            })
+            .collect();
+
+        let total = (width * height) as f32;
+
+        components(&mut changed, width, height)
+            .into_iter()
+            .map(|(x0, y0, x1, y1, cells)| Blob {
+                x: (x0 * scale) as u32,
+                y: (y0 * scale) as u32,
+                width: ((x1 - x0 + 1) * scale) as u32,
+                height: ((y1 - y0 + 1) * scale) as u32,
+                area: cells as f32 * 100.0 / total,
+            })
+            .filter(|blob| blob.area >= min_area)
+            .collect()
+    }
+}
+
+/// 8-connected areas of set cells, as (x0, y0, x1, y1, cell count) with
+/// inclusive bounds. Clears `cells` on the way.
+fn components(cells: &mut [bool], width: usize, height: usize) -> Vec<(usize, usize, usize, usize, usize)> {
+    let mut found = Vec::new();
+    let mut stack = Vec::new();
+
+    for start in 0..cells.len() {
+        if !cells[start] {
+            continue;
+        }
+
+        cells[start] = false;
+        stack.push(start);
+        let (mut x0, mut y0, mut x1, mut y1, mut count) = (width, height, 0, 0, 0);
+
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i % width, i / width);
+            (x0, y0, x1, y1) = (x0.min(x), y0.min(y), x1.max(x), y1.max(y));
+            count += 1;
+
+            for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+                for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+                    let n = ny * width + nx;
+                    if cells[n] {
+                        cells[n] = false;
+                        stack.push(n);
+                    }
+                }
+            }
+        }
+
+        found.push((x0, y0, x1, y1, count));
+    }
+
+    found
+}
+
+/// Runs the detector on the capture thread and turns motion into events:
+/// a snapshot when one starts and/or a clip from `pre_roll` seconds before
+/// until `post_roll` seconds after the last motion.
+pub struct MotionWatcher {
+    detector: MotionDetector,
+    history: PrerollBuffer,
+    clip: Option<Clip>,
+    /// Clips still being written after their event ended.
+    writers: Vec<thread::JoinHandle<()>>,
+    /// Set while an event is going on.
+    last_motion: Option<Instant>,
+}
+
+/// A clip being written on a thread of its own, so encoding never holds up
+/// the capture loop.
+struct Clip {
+    frames: SyncSender<Arc<Frame>>,
+    /// The format the clip was started with.
+    format: FrameFormat,
+    writer: thread::JoinHandle<()>,
+    dropped: u64,
+}
+
+impl MotionWatcher {
+    pub fn new() -> Self {
+        Self {
+            detector: MotionDetector::new(),
+            history: PrerollBuffer::new(),
+            clip: None,
+            writers: Vec::new(),
+            last_motion: None,
+        }
+    }
+
+    pub fn frame(&mut self, shared: &Arc<Mutex<Motion>>, recording: &Mutex<Recording>, frame: &Frame,
+        interval: (u32, u32)) {
+        let (enabled, threshold, min_area, roi, action, pre_roll, post_roll) = {
+            let motion = shared.lock().unwrap();
+            (motion.enabled, motion.threshold, motion.min_area, motion.roi.clone(), motion.action,
+                motion.pre_roll, motion.post_roll)
+        };
+
+        if !enabled {
+            if self.last_motion.is_some() || !self.history.is_empty() {
+                self.stop(shared, recording);
+                self.detector = MotionDetector::new();
+                self.history.clear();
+            }
+            return;
+        }
+
+        let now = Instant::now();
+        let blobs = self.detector.detect(frame, threshold, min_area, &roi);
+        let peak = blobs.iter().map(|blob| blob.area).fold(0.0, f32::max);
+        let moving = !blobs.is_empty();
+
+        let kept = if action.clip() {
+            Some(self.history.add(frame, Duration::from_secs_f64(pre_roll), MAX_HISTORY_BYTES))
+        } else {
+            self.history.clear();
+            None
+        };
+
+        if moving && self.last_motion.is_none() {
+            self.start(shared, recording, frame, action, interval, pre_roll);
+        } else if self.last_motion.is_some() {
+            match &kept {
+                Some(kept) => self.write_clip(shared, kept),
+                None => self.end_clip(),
+            }
+        }
+
+        if moving {
+            self.last_motion = Some(now);
+        }
+
+        let mut motion = shared.lock().unwrap();
+        motion.blobs = blobs;
+        if let Some(event) = motion.events.back_mut().filter(|event| event.end.is_none()) {
+            event.peak = event.peak.max(peak);
+        }
+        drop(motion);
+
+        let quiet = self.last_motion.is_some_and(|last| now.duration_since(last).as_secs_f64() > post_roll);
+        if quiet {
+            self.stop(shared, recording);
+        }
+    }
+
+    /// Ends the current event, if any. Its clip is finished on its writer
+    /// thread.
+    pub fn stop(&mut self, shared: &Mutex<Motion>, recording: &Mutex<Recording>) {
+        self.end_clip();
+
+        let mut motion = shared.lock().unwrap();
+        motion.blobs.clear();
+        motion.active = false;
+
+        if self.last_motion.take().is_none() {
+            return;
+        }
+
+        let dir = recording.lock().unwrap().dir.clone();
+
+        if let Some(event) = motion.events.back_mut() {
+            let end = SystemTime::now();
+            event.end = Some(end);
+
+            println!("motion ended after {:.1} s", end.duration_since(event.start).unwrap_or_default().as_secs_f64());
+
+            if let Err(er) = log_event(&dir, event) {
+                println!("Failed to log motion event: {}", er);
+                motion.error = Some(er.to_string());
+            }
+        }
+    }
+
+    /// Waits for the clips still being written, so they are complete when
+    /// capture ends.
+    pub fn wait(&mut self) {
+        for writer in self.writers.drain(..) {
+            let _ = writer.join();
+        }
+    }
+
+    fn start(&mut self, shared: &Arc<Mutex<Motion>>, recording: &Mutex<Recording>, frame: &Frame,
+        action: MotionAction, interval: (u32, u32), pre_roll: f64) {
+        let (dir, y4m_420) = {
+            let recording = recording.lock().unwrap();
+            (recording.dir.clone(), recording.y4m_420)
+        };
+
+        let mut files = Vec::new();
+        let mut error = None;
+
+        if action.snapshot() {
+            let path = dir.join(snapshot::timestamped_name("motion", ImageFormat::Jpeg.extension()));
+            let frame = frame.clone();
+            files.push(path.clone());
+
+            // encoding can take a moment, the capture loop shouldn't wait
+            let dir = dir.clone();
+            thread::spawn(move || {
+                let result = fs::create_dir_all(&dir)
+                    .and_then(|_| snapshot::write_image(&frame, &path, ImageFormat::Jpeg));
+                if let Err(er) = result {
+                    println!("Failed to write {}: {}", path.display(), er);
+                }
+            });
+        }
+
+        if action.clip() {
+            match self.start_clip(shared, &dir, interval, y4m_420, pre_roll) {
+                Ok(path) => files.push(path),
+                Err(er) => {
+                    println!("Failed to start motion clip: {}", er);
+                    error = Some(er.to_string());
+                },
+            }
+        }
+
+        println!("motion detected at {}", snapshot::timestamp());
+
+        let mut motion = shared.lock().unwrap();
+        motion.active = true;
+        motion.error = error;
+        if motion.events.len() >= MAX_EVENTS {
+            motion.events.pop_front();
+        }
+        motion.events.push_back(MotionEvent {
+            start: SystemTime::now(),
+            end: None,
+            peak: 0.0,
+            files,
+        });
+    }
+
+    /// Opens a clip and hands it to a writer thread, together with the
+    /// buffered frames in the newest one's format, the current one included.
+    fn start_clip(&mut self, shared: &Arc<Mutex<Motion>>, dir: &Path, interval: (u32, u32), y4m_420: bool,
+        pre_roll: f64) -> io::Result<PathBuf> {
+        let history = self.history.recent(Duration::from_secs_f64(pre_roll));
+        let first = history.first().ok_or(io::Error::new(io::ErrorKind::InvalidInput, "no frames buffered"))?;
+        let format = FrameFormat::of(first);
+        let (path, mut writer) = Writer::for_frame(dir, "motion", first, interval, y4m_420)?;
+
+        println!("recording motion to {}", path.display());
+
+        let (frames, queue) = mpsc::sync_channel::<Arc<Frame>>(CLIP_QUEUE);
+        let (shared, thread_path) = (shared.clone(), path.clone());
+
+        let writer = thread::spawn(move || {
+            // the queue ends when the clip does, or as soon as a write fails
+            let written = history.into_iter().chain(queue)
+                .try_for_each(|frame| writer.write_decoded(&frame));
+            let frames = writer.frames();
+            let result = written.and(writer.finish());
+
+            match result {
+                Ok(()) => println!("recorded {} motion frames to {}", frames, thread_path.display()),
+                Err(er) => {
+                    println!("Motion clip {} stopped: {}", thread_path.display(), er);
+                    shared.lock().unwrap().error = Some(er.to_string());
+                },
+            }
+        });
+
+        self.clip = Some(Clip { frames, format, writer, dropped: 0 });
+
+        Ok(path)
+    }
+
+    fn write_clip(&mut self, shared: &Mutex<Motion>, frame: &Arc<Frame>) {
+        let clip = match &mut self.clip {
+            Some(clip) => clip,
+            None => return,
+        };
+
+        // a file holds one format, a change ends the clip
+        if clip.format != FrameFormat::of(frame) {
+            println!("Motion clip stopped: format changed");
+            shared.lock().unwrap().error = Some(String::from("format changed"));
+            self.end_clip();
+            return;
+        }
+
+        match clip.frames.try_send(frame.clone()) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                if clip.dropped == 0 {
+                    println!("Motion clip writer can't keep up, dropping frames");
+                }
+                clip.dropped += 1;
+            },
+            // the writer gave up and said why
+            Err(TrySendError::Disconnected(_)) => self.end_clip(),
+        }
+    }
+
+    /// Lets the writer thread finish the clip with what it was sent.
+    fn end_clip(&mut self) {
+        self.writers.retain(|writer| !writer.is_finished());
+
+        if let Some(clip) = self.clip.take() {
+            if clip.dropped > 0 {
+                println!("Motion clip dropped {} frames", clip.dropped);
+            }
+            self.writers.push(clip.writer);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FrameFormat {
+    fourcc: [u8; 4],
+    width: u32,
+    height: u32,
+    stride: u32,
+}
+
+impl FrameFormat {
+    fn of(frame: &Frame) -> Self {
+        Self {
+            fourcc: frame.fourcc,
+            width: frame.width,
+            height: frame.height,
+            stride: frame.stride,
+        }
+    }
+}
+
+/// Appends `event` to motion-events.csv in `dir`.
+fn log_event(dir: &Path, event: &MotionEvent) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join("motion-events.csv");
+    let new = !path.exists();
+
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    if new {
+        writeln!(file, "start,end,peak_area,files")?;
+    }
+
+    let files: Vec<String> = event.files.iter().map(|file| file.display().to_string()).collect();
+    writeln!(file, "{},{},{:.2},{}",
+        snapshot::timestamp_at(event.start),
+        event.end.map(snapshot::timestamp_at).unwrap_or_default(),
+        event.peak,
+        files.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use v4l::buffer::Flags;
+    use v4l::timestamp::Timestamp;
+
+    const BACKGROUND: u8 = 50;
+
+    /// A flat frame with a bright square of `size` pixels at `at`.
+    fn frame(fourcc: &[u8; 4], width: u32, height: u32, at: (u32, u32), size: u32, value: u8) -> Frame {
+        let step = if fourcc == b"YUYV" { 2 } else { 1 };
+        let stride = width * step;
+        let mut data = vec![128_u8; (stride * height) as usize];
+
+        for y in 0..height {
+            for x in 0..width {
+                let inside = (at.0..at.0 + size).contains(&x) && (at.1..at.1 + size).contains(&y);
+                data[(y * stride + x * step) as usize] = if inside { value } else { BACKGROUND };
+            }
+        }
+
+        Frame {
+            fourcc: *fourcc,
+            width,
+            height,
+            stride,
+            sequence: 0,
+            timestamp: Timestamp::default(),
+            flags: Flags::empty(),
+            data,
+            jpeg: None,
+        }
+    }
+
+    fn grey(at: (u32, u32), size: u32, value: u8) -> Frame {
+        frame(b"GREY", 160, 120, at, size, value)
+    }
+
+    #[test]
+    fn luma_of_yuyv_skips_chroma() {
+        let plane = luma_plane(&frame(b"YUYV", 8, 4, (0, 0), 2, 200), 2).unwrap();
+
+        assert_eq!((plane.width, plane.height), (4, 2));
+        assert_eq!(plane.data, vec![200, 50, 50, 50, 50, 50, 50, 50]);
+    }
+
+    #[test]
+    fn first_frame_has_no_motion() {
+        let mut detector = MotionDetector::new();
+
+        assert!(detector.detect(&grey((20, 20), 16, 200), 20, 0.0, &[]).is_empty());
+    }
+
+    #[test]
+    fn moved_square_is_found() {
+        for fourcc in [b"GREY", b"YUYV"] {
+            let mut detector = MotionDetector::new();
+            detector.detect(&frame(fourcc, 160, 120, (20, 20), 16, 200), 20, 0.0, &[]);
+            let blobs = detector.detect(&frame(fourcc, 160, 120, (80, 60), 16, 200), 20, 0.0, &[]);
+
+            // where it was and where it is now
+            assert_eq!(blobs.len(), 2);
+            assert!(blobs.contains(&Blob { x: 80, y: 60, width: 16, height: 16, area: 256.0 * 100.0 / 19200.0 }));
+        }
+    }
+
+    #[test]
+    fn motion_outside_the_regions_is_ignored() {
+        let left = [Roi::from_corners((0.0, 0.0), (0.5, 1.0))];
+        let right = [Roi::from_corners((0.5, 0.0), (1.0, 1.0))];
+
+        let mut detector = MotionDetector::new();
+        detector.detect(&grey((100, 20), 16, 200), 20, 0.0, &left);
+        assert!(detector.detect(&grey((120, 60), 16, 200), 20, 0.0, &left).is_empty());
+
+        let mut detector = MotionDetector::new();
+        detector.detect(&grey((100, 20), 16, 200), 20, 0.0, &right);
+        assert_eq!(detector.detect(&grey((120, 60), 16, 200), 20, 0.0, &right).len(), 2);
+    }
+
+    #[test]
+    fn small_changes_are_ignored() {
+        // below the threshold
+        let mut detector = MotionDetector::new();
+        detector.detect(&grey((20, 20), 16, BACKGROUND), 20, 0.0, &[]);
+        assert!(detector.detect(&grey((20, 20), 16, BACKGROUND + 15), 20, 0.0, &[]).is_empty());
+        assert_eq!(detector.detect(&grey((20, 20), 16, BACKGROUND + 40), 20, 0.0, &[]).len(), 1);
+
+        // 16 of 19200 cells is 0.083 %
+        let mut detector = MotionDetector::new();
+        detector.detect(&grey((0, 0), 0, 0), 20, 0.1, &[]);
+        assert!(detector.detect(&grey((20, 20), 4, 200), 20, 0.1, &[]).is_empty());
+        assert_eq!(detector.detect(&grey((80, 80), 4, 200), 20, 0.05, &[]).len(), 2);
+    }
+
+    #[test]
+    fn size_change_starts_over() {
+        let mut detector = MotionDetector::new();
+        detector.detect(&grey((20, 20), 16, 200), 20, 0.0, &[]);
+
+        // 320x240 makes a 160x120 plane too, which must not be compared
+        let bigger = frame(b"GREY", 320, 240, (200, 100), 40, 200);
+        assert!(detector.detect(&bigger, 20, 0.0, &[]).is_empty());
+
+        let moved = frame(b"GREY", 320, 240, (100, 100), 40, 200);
+        assert_eq!(detector.detect(&moved, 20, 0.0, &[]).len(), 2);
+    }
+}
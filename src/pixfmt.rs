@@ -0,0 +1,295 @@
+use sdl2::pixels::PixelFormatEnum;
+
+use crate::bayer;
+use crate::frame::Frame;
+
+/// Pixel formats the capture and render pipeline can handle, besides the raw
+/// Bayer formats in `bayer`.
+pub const SUPPORTED: &[[u8; 4]] = &[
+    *b"YUYV", *b"YVYU", *b"UYVY",
+    *b"NV12", *b"NV21",
+    *b"YU12", *b"YV12",
+    *b"RGB3", *b"BGR3",
+    *b"GREY", *b"Y16 ",
+    *b"MJPG",
+];
+
+pub fn is_supported(fourcc: &[u8; 4]) -> bool {
+    SUPPORTED.contains(fourcc) || bayer::is_bayer(fourcc)
+}
+
+/// SDL texture format a frame of `fourcc` is shown with. Formats SDL has no
+/// texture for are converted to RGB24 on upload, MJPG frames arrive already
+/// decoded to RGBA and Bayer frames demosaiced to RGB24.
+pub fn texture_format(fourcc: &[u8; 4]) -> Option<PixelFormatEnum> {
+    if bayer::is_bayer(fourcc) {
+        return Some(PixelFormatEnum::RGB24);
+    }
+
+    let pix_fmt = match fourcc {
+        b"YUYV" => PixelFormatEnum::YUY2,
+        b"YVYU" => PixelFormatEnum::YVYU,
+        b"UYVY" => PixelFormatEnum::UYVY,
+        b"NV12" => PixelFormatEnum::NV12,
+        b"NV21" => PixelFormatEnum::NV21,
+        b"YU12" => PixelFormatEnum::IYUV,
+        b"YV12" => PixelFormatEnum::YV12,
+        b"RGB3" => PixelFormatEnum::RGB24,
+        b"BGR3" => PixelFormatEnum::BGR24,
+        b"GREY" | b"Y16 " => PixelFormatEnum::RGB24,
+        b"MJPG" => PixelFormatEnum::RGBA32,
+        _ => return None,
+    };
+
+    Some(pix_fmt)
+}
+
+/// Row length in bytes and number of rows of each plane, for a frame whose
+/// first plane has `stride` bytes per line. Chroma planes of the 4:2:0
+/// formats follow the luma plane in the same buffer.
+pub fn planes(fourcc: &[u8; 4], stride: usize, height: usize) -> Vec<(usize, usize)> {
+    let chroma_height = height.div_ceil(2);
+
+    match fourcc {
+        b"NV12" | b"NV21" => vec![(stride, height), (stride, chroma_height)],
+        b"YU12" | b"YV12" => {
+            let chroma_stride = stride.div_ceil(2);
+            vec![(stride, height), (chroma_stride, chroma_height), (chroma_stride, chroma_height)]
+        },
+        _ => vec![(stride, height)],
+    }
+}
+
+/// Number of bytes a complete frame takes in the capture buffer.
+pub fn frame_len(fourcc: &[u8; 4], stride: usize, height: usize) -> usize {
+    planes(fourcc, stride, height)
+        .iter()
+        .map(|(row, rows)| row * rows)
+        .sum()
+}
+
+/// Copies a frame into a locked SDL texture with rows of `pitch` bytes,
+/// converting the formats SDL can't show natively.
+pub fn upload(frame: &Frame, buffer: &mut [u8], pitch: usize) {
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let stride = frame.stride as usize;
+    let data = &frame.data[..];
+
+    match &frame.fourcc {
+        b"GREY" => {
+            for (dst, src) in buffer.chunks_mut(pitch).zip(data.chunks(stride)).take(height) {
+                for (pixel, &y) in dst.chunks_exact_mut(3).zip(&src[..width.min(src.len())]) {
+                    pixel.fill(y);
+                }
+            }
+        },
+        b"Y16 " => {
+            // little endian 16 bit luminance, keep the high byte
+            for (dst, src) in buffer.chunks_mut(pitch).zip(data.chunks(stride)).take(height) {
+                for (pixel, y) in dst.chunks_exact_mut(3).zip(src.chunks_exact(2).take(width)) {
+                    pixel.fill(y[1]);
+                }
+            }
+        },
+        _ => {
+            let src_planes = planes(&frame.fourcc, stride, height);
+            let dst_planes = planes(&frame.fourcc, pitch, height);
+
+            let (mut src_offset, mut dst_offset) = (0, 0);
+
+            for ((src_row, rows), (dst_row, _)) in src_planes.into_iter().zip(dst_planes) {
+                if src_offset >= data.len() || dst_offset >= buffer.len() {
+                    break;
+                }
+
+                if src_row == dst_row {
+                    let len = (src_row * rows)
+                        .min(data.len().saturating_sub(src_offset))
+                        .min(buffer.len().saturating_sub(dst_offset));
+                    buffer[dst_offset..dst_offset + len]
+                        .copy_from_slice(&data[src_offset..src_offset + len]);
+                } else {
+                    // driver and texture rows are padded differently, copy row by row
+                    let row = src_row.min(dst_row);
+                    let src = data.get(src_offset..).unwrap_or_default();
+                    let dst = buffer.get_mut(dst_offset..).unwrap_or_default();
+
+                    for (dst, src) in dst.chunks_mut(dst_row).zip(src.chunks(src_row)).take(rows) {
+                        let row = row.min(src.len()).min(dst.len());
+                        dst[..row].copy_from_slice(&src[..row]);
+                    }
+                }
+
+                src_offset += src_row * rows;
+                dst_offset += dst_row * rows;
+            }
+        }
+    }
+}
+
+/// BT.601 limited range YUV to RGB, as UVC cameras deliver it.
+fn yuv_to_rgb(y: u8, u: u8, v: u8) -> [u8; 3] {
+    let c = (y as i32 - 16) * 298;
+    let d = u as i32 - 128;
+    let e = v as i32 - 128;
+
+    let clamp = |x: i32| ((x + 128) >> 8).clamp(0, 255) as u8;
+
+    [clamp(c + 409 * e), clamp(c - 100 * d - 208 * e), clamp(c + 516 * d)]
+}
+
+/// Converts a frame to packed 8 bit RGB, for saving it as an image. Returns
+/// `None` for formats we can't convert or frames shorter than their size.
+pub fn to_rgb(frame: &Frame) -> Option<Vec<u8>> {
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let stride = frame.stride as usize;
+    let data = &frame.data[..];
+
+    // already RGB(A) after decoding or demosaicing
+    if &frame.fourcc == b"MJPG" {
+        let rgba = data.get(..width * height * 4)?;
+        return Some(rgba.chunks_exact(4).flat_map(|p| [p[0], p[1], p[2]]).collect());
+    }
+    if bayer::is_bayer(&frame.fourcc) {
+        return Some(data.get(..width * height * 3)?.to_vec());
+    }
+
+    if !SUPPORTED.contains(&frame.fourcc) || data.len() < frame_len(&frame.fourcc, stride, height) {
+        return None;
+    }
+
+    let planes = planes(&frame.fourcc, stride, height);
+    let chroma_offset = planes[0].0 * planes[0].1;
+    let second_offset = chroma_offset + planes.get(1).map_or(0, |(row, rows)| row * rows);
+    let chroma_stride = planes.get(1).map_or(0, |(row, _)| *row);
+
+    let mut rgb = Vec::with_capacity(width * height * 3);
+
+    for y in 0..height {
+        let row = &data[y * stride..];
+
+        for x in 0..width {
+            let pixel = match &frame.fourcc {
+                b"YUYV" | b"YVYU" | b"UYVY" => {
+                    let pair = &row[x / 2 * 4..x / 2 * 4 + 4];
+                    match &frame.fourcc {
+                        b"YUYV" => yuv_to_rgb(pair[x % 2 * 2], pair[1], pair[3]),
+                        b"YVYU" => yuv_to_rgb(pair[x % 2 * 2], pair[3], pair[1]),
+                        _ => yuv_to_rgb(pair[1 + x % 2 * 2], pair[0], pair[2]),
+                    }
+                },
+                b"NV12" | b"NV21" => {
+                    let uv = chroma_offset + y / 2 * chroma_stride + x / 2 * 2;
+                    let (u, v) = (data[uv], data[uv + 1]);
+                    if &frame.fourcc == b"NV12" {
+                        yuv_to_rgb(row[x], u, v)
+                    } else {
+                        yuv_to_rgb(row[x], v, u)
+                    }
+                },
+                b"YU12" | b"YV12" => {
+                    let c = y / 2 * chroma_stride + x / 2;
+                    let (first, second) = (data[chroma_offset + c], data[second_offset + c]);
+                    if &frame.fourcc == b"YU12" {
+                        yuv_to_rgb(row[x], first, second)
+                    } else {
+                        yuv_to_rgb(row[x], second, first)
+                    }
+                },
+                b"RGB3" => [row[x * 3], row[x * 3 + 1], row[x * 3 + 2]],
+                b"BGR3" => [row[x * 3 + 2], row[x * 3 + 1], row[x * 3]],
+                b"GREY" => [row[x]; 3],
+                b"Y16 " => [row[x * 2 + 1]; 3],
+                _ => return None,
+            };
+
+            rgb.extend_from_slice(&pixel);
+        }
+    }
+
+    Some(rgb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use v4l::buffer::Flags;
+    use v4l::timestamp::Timestamp;
+
+    fn frame(fourcc: &[u8; 4], width: u32, height: u32, stride: u32, data: Vec<u8>) -> Frame {
+        Frame {
+            fourcc: *fourcc,
+            width,
+            height,
+            stride,
+            sequence: 0,
+            timestamp: Timestamp::default(),
+            flags: Flags::empty(),
+            data,
+            jpeg: None,
+        }
+    }
+
+    #[test]
+    fn plane_layout() {
+        assert_eq!(planes(b"YUYV", 1280, 480), vec![(1280, 480)]);
+        assert_eq!(planes(b"NV12", 640, 480), vec![(640, 480), (640, 240)]);
+        assert_eq!(planes(b"YU12", 5, 3), vec![(5, 3), (3, 2), (3, 2)]);
+
+        assert_eq!(frame_len(b"YUYV", 1280, 480), 1280 * 480);
+        assert_eq!(frame_len(b"NV21", 640, 480), 640 * 480 * 3 / 2);
+        assert_eq!(frame_len(b"YV12", 5, 3), 15 + 6 + 6);
+    }
+
+    #[test]
+    fn packed_yuv_to_rgb() {
+        // black and white pixel pair, no colour
+        let yuyv = frame(b"YUYV", 2, 1, 4, vec![16, 128, 235, 128]);
+        assert_eq!(to_rgb(&yuyv), Some(vec![0, 0, 0, 255, 255, 255]));
+
+        let uyvy = frame(b"UYVY", 2, 1, 4, vec![128, 235, 128, 16]);
+        assert_eq!(to_rgb(&uyvy), Some(vec![255, 255, 255, 0, 0, 0]));
+
+        // U and V swap places between the two orders
+        let yuyv = frame(b"YUYV", 2, 1, 4, vec![81, 90, 81, 240]);
+        let yvyu = frame(b"YVYU", 2, 1, 4, vec![81, 240, 81, 90]);
+        let red = to_rgb(&yuyv).unwrap();
+        assert_eq!(to_rgb(&yvyu).as_ref(), Some(&red));
+        assert!(red[0] > 240 && red[1] < 10 && red[2] < 10, "{:?}", red);
+    }
+
+    #[test]
+    fn planar_yuv_to_rgb() {
+        // 2x2 frames with rows padded to 4 bytes, one chroma sample
+        let nv12 = frame(b"NV12", 2, 2, 4, vec![81, 81, 0, 0, 81, 81, 0, 0, 90, 240, 0, 0]);
+        let nv21 = frame(b"NV21", 2, 2, 4, vec![81, 81, 0, 0, 81, 81, 0, 0, 240, 90, 0, 0]);
+        let yu12 = frame(b"YU12", 2, 2, 4, vec![81, 81, 0, 0, 81, 81, 0, 0, 90, 0, 240, 0]);
+        let yv12 = frame(b"YV12", 2, 2, 4, vec![81, 81, 0, 0, 81, 81, 0, 0, 240, 0, 90, 0]);
+
+        let red = to_rgb(&frame(b"YUYV", 2, 1, 4, vec![81, 90, 81, 240])).unwrap();
+        let expected: Vec<u8> = red.repeat(2);
+        for frame in [nv12, nv21, yu12, yv12] {
+            assert_eq!(to_rgb(&frame).as_ref(), Some(&expected), "{}", frame.fourcc_str());
+        }
+    }
+
+    #[test]
+    fn rgb_and_grey() {
+        let bgr = frame(b"BGR3", 1, 1, 3, vec![1, 2, 3]);
+        assert_eq!(to_rgb(&bgr), Some(vec![3, 2, 1]));
+
+        let grey = frame(b"GREY", 2, 1, 2, vec![7, 9]);
+        assert_eq!(to_rgb(&grey), Some(vec![7, 7, 7, 9, 9, 9]));
+
+        // little endian, the high byte is kept
+        let y16 = frame(b"Y16 ", 1, 1, 2, vec![0x34, 0x12]);
+        assert_eq!(to_rgb(&y16), Some(vec![0x12; 3]));
+    }
+
+    #[test]
+    fn refuses_short_or_unknown_frames() {
+        assert_eq!(to_rgb(&frame(b"YUYV", 2, 2, 4, vec![0; 6])), None);
+        assert_eq!(to_rgb(&frame(b"H264", 2, 2, 4, vec![0; 16])), None);
+        assert_eq!(to_rgb(&frame(b"MJPG", 2, 2, 8, vec![0; 15])), None);
+    }
+}
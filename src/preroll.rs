@@ -0,0 +1,200 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::frame::Frame;
+use crate::record::{Recording, StreamParams, Writer};
+use crate::snapshot::{self, ImageFormat};
+
+/// Pre-roll settings and status, shared by the GUI and the capture thread.
+/// The GUI asks for a save with `request_save`; the capture thread hands the
+/// buffered frames to a writer thread and leaves the outcome in `last`.
+pub struct Preroll {
+    pub enabled: bool,
+    /// How much history to keep, whichever limit is hit first.
+    pub seconds: f64,
+    pub max_bytes: u64,
+    /// How much of the history a save writes out.
+    pub save_seconds: f64,
+    pub frames: usize,
+    pub bytes: u64,
+    pub saving: bool,
+    pub last: Option<Result<PathBuf, String>>,
+    requested: bool,
+}
+
+impl Preroll {
+    pub fn new(seconds: f64, max_bytes: u64) -> Self {
+        Self {
+            enabled: seconds > 0.0,
+            seconds: seconds.max(1.0),
+            max_bytes,
+            save_seconds: seconds.max(1.0),
+            frames: 0,
+            bytes: 0,
+            saving: false,
+            last: None,
+            requested: false,
+        }
+    }
+
+    pub fn request_save(&mut self) {
+        self.requested = true;
+    }
+}
+
+/// The ring buffer itself, owned by the capture thread. Frames are kept as
+/// handed to the renderer, except that decoded MJPG pixels are left out and
+/// only the original JPEG bytes are held on to. They are shared with the
+/// writer thread, so starting a save only copies references.
+pub struct PrerollBuffer {
+    frames: VecDeque<(Instant, Arc<Frame>)>,
+    bytes: u64,
+}
+
+impl PrerollBuffer {
+    pub fn new() -> Self {
+        Self {
+            frames: VecDeque::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Adds `frame`, trims the history to the configured limits and starts
+    /// a save if one was asked for. Saves go to the recording directory.
+    pub fn push(&mut self, shared: &Arc<Mutex<Preroll>>, recording: &Mutex<Recording>,
+        frame: &Frame, interval: (u32, u32)) {
+        let mut preroll = shared.lock().unwrap();
+
+        if !preroll.enabled {
+            if !self.frames.is_empty() {
+                self.clear();
+                preroll.frames = 0;
+                preroll.bytes = 0;
+            }
+            preroll.requested = false;
+            return;
+        }
+
+        self.add(frame, Duration::from_secs_f64(preroll.seconds), preroll.max_bytes);
+
+        preroll.frames = self.frames.len();
+        preroll.bytes = self.bytes;
+
+        if std::mem::take(&mut preroll.requested) && !preroll.saving {
+            let frames = self.recent(Duration::from_secs_f64(preroll.save_seconds));
+
+            preroll.saving = true;
+            let (dir, y4m_420) = {
+                let recording = recording.lock().unwrap();
+                (recording.dir.clone(), recording.y4m_420)
+            };
+            let shared = shared.clone();
+
+            // writing takes a while, the capture loop and preview must not wait
+            thread::spawn(move || {
+                let result = save(&frames, &dir, interval, y4m_420);
+
+                match &result {
+                    Ok(path) => println!("saved {} pre-roll frames to {}", frames.len(), path.display()),
+                    Err(er) => println!("Failed to save pre-roll: {}", er),
+                }
+
+                let mut preroll = shared.lock().unwrap();
+                preroll.saving = false;
+                preroll.last = Some(result.map_err(|er| er.to_string()));
+            });
+        }
+    }
+
+    /// Adds `frame` and drops what is older than `keep`, then the oldest
+    /// frames until the rest fit in `max_bytes`. Returns the frame as kept.
+    pub fn add(&mut self, frame: &Frame, keep: Duration, max_bytes: u64) -> Arc<Frame> {
+        let frame = Arc::new(match &frame.jpeg {
+            Some(jpeg) => Frame { data: Vec::new(), jpeg: Some(jpeg.clone()), ..*frame },
+            None => frame.clone(),
+        });
+
+        let now = Instant::now();
+        self.bytes += frame_bytes(&frame);
+        self.frames.push_back((now, frame.clone()));
+
+        while let Some((time, oldest)) = self.frames.front() {
+            if now.duration_since(*time) <= keep && self.bytes <= max_bytes {
+                break;
+            }
+            self.bytes -= frame_bytes(oldest);
+            self.frames.pop_front();
+        }
+
+        frame
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.bytes = 0;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Frames from the last `keep`, limited to those in the newest frame's
+    /// format, since a file can only hold one.
+    pub fn recent(&self, keep: Duration) -> Vec<Arc<Frame>> {
+        let now = Instant::now();
+        let newest = match self.frames.back() {
+            Some((_, frame)) => (frame.fourcc, frame.width, frame.height, frame.stride),
+            None => return Vec::new(),
+        };
+
+        let mut frames: Vec<Arc<Frame>> = self.frames.iter()
+            .rev()
+            .take_while(|(time, frame)| now.duration_since(*time) <= keep
+                && (frame.fourcc, frame.width, frame.height, frame.stride) == newest)
+            .map(|(_, frame)| frame.clone())
+            .collect();
+
+        frames.reverse();
+        frames
+    }
+}
+
+fn frame_bytes(frame: &Frame) -> u64 {
+    (frame.data.len() + frame.jpeg.as_ref().map_or(0, |jpeg| jpeg.len())) as u64
+}
+
+/// Writes `frames` as MJPEG AVI, Y4M or a PNG sequence, whichever fits the
+/// format, plus a CSV of their sequence numbers and timestamps.
+fn save(frames: &[Arc<Frame>], dir: &Path, interval: (u32, u32), y4m_420: bool) -> io::Result<PathBuf> {
+    let first = frames.first().ok_or(io::Error::new(io::ErrorKind::InvalidInput, "nothing buffered"))?;
+
+    let path = if first.jpeg.is_some() || Writer::stores_as_is(&first.fourcc, y4m_420) {
+        let (path, mut writer) = Writer::create(dir, "preroll", &StreamParams::of_frame(first, interval, y4m_420))?;
+        for frame in frames {
+            writer.write_frame(frame.jpeg.as_deref().unwrap_or(&frame.data))?;
+        }
+        writer.finish()?;
+        path
+    } else {
+        let path = dir.join(format!("preroll-{}", snapshot::timestamp()));
+        fs::create_dir_all(&path)?;
+        for (i, frame) in frames.iter().enumerate() {
+            snapshot::write_image(frame, &path.join(format!("frame-{:06}.png", i)), ImageFormat::Png)?;
+        }
+        path
+    };
+
+    let mut csv = BufWriter::new(fs::File::create(path.with_extension("csv"))?);
+    writeln!(csv, "frame,sequence,timestamp")?;
+    for (i, frame) in frames.iter().enumerate() {
+        writeln!(csv, "{},{},{}.{:06}", i, frame.sequence, frame.timestamp.sec, frame.timestamp.usec)?;
+    }
+    csv.flush()?;
+
+    Ok(path)
+}
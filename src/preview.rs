@@ -0,0 +1,254 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use eframe::egui;
+use v4l::buffer::Flags as BufferFlags;
+
+use crate::frame::{Frame, FrameSlot};
+use crate::motion::{Motion, Roi};
+use crate::pixfmt;
+use crate::record::Recording;
+use crate::snapshot::Snapshot;
+
+/// Where the live picture is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreviewBackend {
+    /// A window of its own, drawn by SDL on the render thread.
+    Sdl,
+    /// A panel of the GUI window, next to the tabs.
+    Embedded,
+}
+
+impl PreviewBackend {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "sdl" => Some(PreviewBackend::Sdl),
+            "embedded" => Some(PreviewBackend::Embedded),
+            _ => None,
+        }
+    }
+}
+
+/// What the conversion thread leaves for the GUI.
+#[derive(Default)]
+struct Latest {
+    image: Option<egui::ColorImage>,
+    fps: f64,
+    paused: bool,
+}
+
+/// The preview inside the GUI window. Frames are converted on a thread of
+/// their own, which wakes the GUI up for each one; the GUI thread only
+/// uploads them to a texture.
+pub struct Preview {
+    latest: Arc<Mutex<Latest>>,
+    frames: Arc<FrameSlot>,
+    texture: Option<egui::TextureHandle>,
+    /// Corners of the motion region being drawn, 0 to 1 across the frame.
+    drag: Option<(egui::Pos2, egui::Pos2)>,
+    recording: Arc<Mutex<Recording>>,
+    motion: Arc<Mutex<Motion>>,
+    snapshot: Arc<Mutex<Snapshot>>,
+}
+
+impl Preview {
+    pub fn new(ctx: &egui::Context, frames: Arc<FrameSlot>, snapshot: Arc<Mutex<Snapshot>>,
+        recording: Arc<Mutex<Recording>>, motion: Arc<Mutex<Motion>>) -> Self {
+        let latest = Arc::new(Mutex::new(Latest::default()));
+
+        let (ctx, thread_latest, thread_frames) = (ctx.clone(), latest.clone(), frames.clone());
+        thread::spawn(move || convert_frames(&ctx, &thread_frames, &thread_latest));
+
+        Self {
+            latest,
+            frames,
+            texture: None,
+            drag: None,
+            recording,
+            motion,
+            snapshot,
+        }
+    }
+
+    /// Draws the newest frame into the space left in `ui`, keeping its
+    /// aspect ratio, with the frame rate below. Takes the preview window's
+    /// keys while no text field has focus, and the mouse for motion regions.
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        let (image, fps, paused) = {
+            let mut latest = self.latest.lock().unwrap();
+            (latest.image.take(), latest.fps, latest.paused)
+        };
+
+        if let Some(image) = image {
+            match &mut self.texture {
+                Some(texture) => texture.set(image, egui::TextureOptions::LINEAR),
+                None => self.texture = Some(ui.ctx().load_texture("preview", image, egui::TextureOptions::LINEAR)),
+            }
+        }
+
+        self.handle_keys(ui);
+
+        let status_height = ui.text_style_height(&egui::TextStyle::Body) + ui.spacing().item_spacing.y;
+        let available = ui.available_size() - egui::vec2(0.0, status_height);
+        let (rect, response) = ui.allocate_exact_size(available.max(egui::Vec2::ZERO), egui::Sense::click_and_drag());
+
+        ui.painter().rect_filled(rect, 0.0, egui::Color32::BLACK);
+
+        if let Some(texture) = &self.texture {
+            let size = texture.size_vec2();
+            let scale = (rect.width() / size.x).min(rect.height() / size.y);
+            let image_rect = egui::Rect::from_center_size(rect.center(), size * scale);
+
+            ui.painter().image(texture.id(), image_rect,
+                egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)), egui::Color32::WHITE);
+
+            self.handle_mouse(&response, image_rect);
+            self.draw_motion(ui, image_rect, size);
+        }
+
+        let mut status = format!("{:.2} fps - {} dropped", fps, self.frames.dropped());
+        if paused {
+            status.push_str(" - paused");
+        }
+        if self.recording.lock().unwrap().active {
+            status.push_str(" - REC");
+        }
+        if self.motion.lock().unwrap().active {
+            status.push_str(" - MOTION");
+        }
+        ui.label(status);
+    }
+
+    /// S saves a snapshot, R starts or stops recording and Space pauses,
+    /// as in the SDL window.
+    fn handle_keys(&self, ui: &egui::Ui) {
+        if ui.ctx().wants_keyboard_input() {
+            return;
+        }
+
+        let (snapshot, record, pause) = ui.input(|input| (
+            input.key_pressed(egui::Key::S),
+            input.key_pressed(egui::Key::R),
+            input.key_pressed(egui::Key::Space),
+        ));
+
+        if snapshot {
+            self.snapshot.lock().unwrap().request();
+        }
+        if record {
+            self.recording.lock().unwrap().toggle();
+        }
+        if pause {
+            let mut latest = self.latest.lock().unwrap();
+            latest.paused = !latest.paused;
+        }
+    }
+
+    /// Dragging adds a motion region, a right click clears them.
+    fn handle_mouse(&mut self, response: &egui::Response, image_rect: egui::Rect) {
+        let to_frame = |pos: egui::Pos2| {
+            let pos = (pos - image_rect.min) / image_rect.size();
+            egui::pos2(pos.x.clamp(0., 1.), pos.y.clamp(0., 1.))
+        };
+
+        if response.secondary_clicked() {
+            self.motion.lock().unwrap().roi.clear();
+        }
+
+        if let Some(pointer) = response.interact_pointer_pos().map(to_frame) {
+            if response.drag_started_by(egui::PointerButton::Primary) && self.motion.lock().unwrap().enabled {
+                self.drag = Some((pointer, pointer));
+            } else if let Some((_, end)) = &mut self.drag {
+                *end = pointer;
+            }
+        }
+
+        if response.drag_stopped() {
+            if let Some((start, end)) = self.drag.take() {
+                let roi = Roi::from_corners((start.x, start.y), (end.x, end.y));
+                // a plain click isn't a region
+                if roi.width > 0.01 && roi.height > 0.01 {
+                    self.motion.lock().unwrap().roi.push(roi);
+                }
+            }
+        }
+    }
+
+    /// Motion regions in yellow and what moved in red, while the detector
+    /// is on. Blobs are in frame pixels, `frame_size` of them.
+    fn draw_motion(&self, ui: &egui::Ui, image_rect: egui::Rect, frame_size: egui::Vec2) {
+        let motion = self.motion.lock().unwrap();
+        if !motion.enabled {
+            return;
+        }
+
+        let painter = ui.painter();
+        let region = |x: f32, y: f32, width: f32, height: f32| egui::Rect::from_min_size(
+            image_rect.min + egui::vec2(x, y) * image_rect.size(),
+            egui::vec2(width, height) * image_rect.size(),
+        );
+
+        let stroke = egui::Stroke::new(1.0, egui::Color32::from_rgb(250, 200, 0));
+        let drawing = self.drag.map(|(start, end)| Roi::from_corners((start.x, start.y), (end.x, end.y)));
+        for roi in motion.roi.iter().chain(&drawing) {
+            painter.rect_stroke(region(roi.x, roi.y, roi.width, roi.height), 0.0, stroke);
+        }
+
+        let stroke = egui::Stroke::new(1.0, egui::Color32::from_rgb(255, 40, 40));
+        for blob in &motion.blobs {
+            painter.rect_stroke(region(
+                blob.x as f32 / frame_size.x, blob.y as f32 / frame_size.y,
+                blob.width as f32 / frame_size.x, blob.height as f32 / frame_size.y,
+            ), 0.0, stroke);
+        }
+    }
+}
+
+/// Takes frames as they come and converts them for display. While paused frames are still taken, so the drop
+/// counter stays meaningful, but not converted.
+fn convert_frames(ctx: &egui::Context, frames: &FrameSlot, latest: &Mutex<Latest>) {
+    let mut count = 0_u32;
+    let mut since = Instant::now();
+
+    loop {
+        let frame = match frames.take_timeout(Duration::from_millis(100)) {
+            Some(frame) => frame,
+            None => continue,
+        };
+
+        // the driver flags frames it knows to be corrupted, don't show them
+        if frame.flags.contains(BufferFlags::ERROR) {
+            continue;
+        }
+
+        count += 1;
+
+        let image = if latest.lock().unwrap().paused { None } else { color_image(&frame) };
+
+        let mut latest = latest.lock().unwrap();
+        if image.is_some() {
+            latest.image = image;
+        }
+        if since.elapsed() >= Duration::from_secs(2) {
+            latest.fps = count as f64 / since.elapsed().as_secs_f64();
+            count = 0;
+            since = Instant::now();
+        }
+        drop(latest);
+
+        ctx.request_repaint();
+    }
+}
+
+fn color_image(frame: &Frame) -> Option<egui::ColorImage> {
+    let size = [frame.width as usize, frame.height as usize];
+
+    // MJPG arrives decoded to RGBA
+    if &frame.fourcc == b"MJPG" {
+        let rgba = frame.data.get(..size[0] * size[1] * 4)?;
+        return Some(egui::ColorImage::from_rgba_unmultiplied(size, rgba));
+    }
+
+    Some(egui::ColorImage::from_rgb(size, &pixfmt::to_rgb(frame)?))
+}
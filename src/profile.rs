@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use v4l::control::{self, Value};
+use v4l::Device;
+
+use crate::controls;
+use crate::devices::DeviceInfo;
+
+/// How often a batch of rejected controls is retried. Every round that gets
+/// at least one control through may have activated others.
+const MAX_ROUNDS: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ProfileValue {
+    Boolean(bool),
+    Integer(i64),
+    String(String),
+}
+
+impl ProfileValue {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Boolean(v) => Some(Self::Boolean(*v)),
+            Value::Integer(v) => Some(Self::Integer(*v)),
+            Value::String(v) => Some(Self::String(v.clone())),
+            _ => None,
+        }
+    }
+
+    /// The value as the control's current type wants it.
+    fn to_value(&self, typ: control::Type) -> Value {
+        match (self, typ) {
+            (Self::Boolean(v), control::Type::Boolean) => Value::Boolean(*v),
+            (Self::Integer(v), control::Type::Boolean) => Value::Boolean(*v != 0),
+            (Self::Boolean(v), _) => Value::Integer(*v as i64),
+            (Self::Integer(v), _) => Value::Integer(*v),
+            (Self::String(v), _) => Value::String(v.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileControl {
+    pub id: u32,
+    /// Only for people reading the file, and a fallback should the id change.
+    pub name: String,
+    pub value: ProfileValue,
+}
+
+/// Control values plus the format, size and rate they were saved with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub fourcc: String,
+    pub width: u32,
+    pub height: u32,
+    pub interval: (u32, u32),
+    pub controls: Vec<ProfileControl>,
+}
+
+/// A control the driver wouldn't take.
+#[derive(Debug, Clone)]
+pub struct Rejection {
+    pub name: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.reason)
+    }
+}
+
+impl Profile {
+    /// Reads every writable control of `dev`. Inactive controls are kept too,
+    /// they become active again with the mode they were saved under.
+    pub fn capture(dev: &Device, fourcc: [u8; 4], size: (u32, u32), interval: (u32, u32))
+        -> Result<Self, String> {
+        let descriptions = dev.query_controls().map_err(|er| format!("Couldn't query controls: {}", er))?;
+
+        let skip = control::Flags::READ_ONLY | control::Flags::WRITE_ONLY
+            | control::Flags::DISABLED | control::Flags::VOLATILE;
+
+        let controls = descriptions.iter()
+            .filter(|desc| !desc.flags.intersects(skip))
+            .filter(|desc| !matches!(desc.typ, control::Type::CtrlClass | control::Type::Button))
+            .filter_map(|desc| {
+                let value = ProfileValue::from_value(&controls::read(dev, desc).ok()?)?;
+                Some(ProfileControl { id: desc.id, name: desc.name.clone(), value })
+            })
+            .collect();
+
+        Ok(Self {
+            fourcc: String::from_utf8_lossy(&fourcc).trim_end().to_string(),
+            width: size.0,
+            height: size.1,
+            interval,
+            controls,
+        })
+    }
+
+    pub fn fourcc(&self) -> Result<[u8; 4], String> {
+        crate::cli::parse_fourcc(&self.fourcc)
+    }
+
+    /// Writes the saved control values to `dev` and returns those it refused.
+    pub fn apply_controls(&self, dev: &Device) -> Vec<Rejection> {
+        write_controls(dev, &self.controls)
+    }
+}
+
+/// Writes `controls` to `dev` and returns those it refused.
+///
+/// Mode controls (menus and switches such as `auto_exposure` or
+/// `white_balance_automatic`) go first, since they decide whether the
+/// values they gate can be written at all. Whatever is refused is tried
+/// again once the rest is in, for dependencies that don't follow that
+/// rule. Controls that end up inactive are left alone rather than
+/// reported, the mode controls written along with them switched them off.
+pub fn write_controls(dev: &Device, controls: &[ProfileControl]) -> Vec<Rejection> {
+    let descriptions = match dev.query_controls() {
+        Ok(descriptions) => descriptions,
+        Err(er) => return vec![Rejection { name: String::from("controls"), reason: er.to_string() }],
+    };
+
+    let mut rejected = Vec::new();
+    let mut pending = Vec::new();
+
+    for saved in controls {
+        let desc = descriptions.iter().find(|desc| desc.id == saved.id)
+            .or_else(|| descriptions.iter().find(|desc| desc.name == saved.name));
+
+        match desc {
+            None => rejected.push(Rejection { name: saved.name.clone(), reason: String::from("no such control") }),
+            Some(desc) if desc.flags.intersects(control::Flags::READ_ONLY) =>
+                rejected.push(Rejection { name: desc.name.clone(), reason: String::from("read-only") }),
+            Some(desc) => pending.push((desc.id, desc.name.clone(), desc.typ, saved.value.clone())),
+        }
+    }
+
+    pending.sort_by_key(|(_, _, typ, _)| !matches!(typ, control::Type::Menu | control::Type::Boolean));
+
+    let mut failed = Vec::new();
+
+    for _ in 0..MAX_ROUNDS {
+        let tried = pending.len();
+        failed.clear();
+
+        for (id, name, typ, value) in pending.drain(..) {
+            if let Err(er) = controls::write(dev, id, value.to_value(typ)) {
+                failed.push((id, name, typ, value, er.to_string()));
+            }
+        }
+
+        if failed.is_empty() || failed.len() == tried {
+            break;
+        }
+
+        pending = failed.iter().map(|(id, name, typ, value, _)| (*id, name.clone(), *typ, value.clone())).collect();
+    }
+
+    // what's inactive now was switched off by the modes written above
+    let descriptions = dev.query_controls().unwrap_or(descriptions);
+    for (id, name, _, _, reason) in failed {
+        let inactive = descriptions.iter()
+            .any(|desc| desc.id == id && desc.flags.intersects(control::Flags::INACTIVE));
+        if inactive {
+            println!("leaving inactive control {} alone", name);
+        } else {
+            rejected.push(Rejection { name, reason });
+        }
+    }
+
+    rejected
+}
+
+/// All saved profiles, by device and then by name, as kept in one JSON file.
+/// Devices are told apart by card name and bus position, so two cameras of
+/// the same model each get their own.
+pub struct ProfileStore {
+    path: PathBuf,
+    devices: BTreeMap<String, BTreeMap<String, Profile>>,
+}
+
+impl ProfileStore {
+    /// Reads `path`; a file that isn't there yet is an empty store.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let devices = match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|er| format!("{}: {}", path.display(), er))?,
+            Err(er) if er.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(er) => return Err(format!("{}: {}", path.display(), er)),
+        };
+
+        Ok(Self { path: path.to_path_buf(), devices })
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let error = |er: &dyn fmt::Display| format!("{}: {}", self.path.display(), er);
+
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|er| error(&er))?;
+        }
+
+        let text = serde_json::to_string_pretty(&self.devices).map_err(|er| error(&er))?;
+        fs::write(&self.path, text + "\n").map_err(|er| error(&er))
+    }
+
+    /// Profile names saved for `device`, in alphabetical order.
+    pub fn names(&self, device: &DeviceInfo) -> Vec<String> {
+        self.devices.get(&device_key(device))
+            .map(|profiles| profiles.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn get(&self, device: &DeviceInfo, name: &str) -> Option<&Profile> {
+        self.devices.get(&device_key(device))?.get(name)
+    }
+
+    pub fn insert(&mut self, device: &DeviceInfo, name: &str, profile: Profile) {
+        self.devices.entry(device_key(device)).or_default().insert(name.to_string(), profile);
+    }
+
+    pub fn remove(&mut self, device: &DeviceInfo, name: &str) {
+        let key = device_key(device);
+        if let Some(profiles) = self.devices.get_mut(&key) {
+            profiles.remove(name);
+            if profiles.is_empty() {
+                self.devices.remove(&key);
+            }
+        }
+    }
+}
+
+fn device_key(device: &DeviceInfo) -> String {
+    format!("{} @ {}", device.card, device.bus)
+}
+
+/// `$XDG_CONFIG_HOME/rustycamera/profiles.json`, or the same under ~/.config.
+pub fn default_path() -> PathBuf {
+    config_dir().join("profiles.json")
+}
+
+/// `$XDG_CONFIG_HOME/rustycamera`, or ~/.config/rustycamera.
+pub fn config_dir() -> PathBuf {
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_default();
+
+    config.join("rustycamera")
+}
+
+/// Looks up profile `name` of `device` in the file at `path`.
+pub fn find(path: &Path, device: &DeviceInfo, name: &str) -> Result<Profile, String> {
+    let store = ProfileStore::load(path)?;
+
+    store.get(device, name).cloned().ok_or_else(|| {
+        let names = store.names(device);
+        if names.is_empty() {
+            format!("no profiles saved for {} in {}", device_key(device), path.display())
+        } else {
+            format!("no profile {:?} for {}, saved are {}", name, device_key(device), names.join(", "))
+        }
+    })
+}
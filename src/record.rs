@@ -0,0 +1,292 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use v4l::format::Format;
+
+use crate::avi::AviWriter;
+use crate::frame::Frame;
+use crate::pixfmt;
+use crate::snapshot;
+use crate::y4m::{Chroma, Y4mWriter};
+
+/// Recording state shared by the GUI, the preview window and the capture
+/// thread. The first two flip `active`, the capture thread does the writing
+/// and reports back through the remaining fields.
+pub struct Recording {
+    pub dir: PathBuf,
+    /// AVI files are split before they grow past this many bytes.
+    pub split_size: u64,
+    /// Write packed 4:2:2 streams to Y4M as 4:2:0, which more tools read.
+    pub y4m_420: bool,
+    pub active: bool,
+    pub file: Option<PathBuf>,
+    pub frames: u64,
+    pub bytes: u64,
+    pub error: Option<String>,
+}
+
+impl Recording {
+    pub fn new(dir: PathBuf, split_size: u64) -> Self {
+        Self {
+            dir,
+            split_size,
+            y4m_420: false,
+            active: false,
+            file: None,
+            frames: 0,
+            bytes: 0,
+            error: None,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.active = !self.active;
+        if self.active {
+            self.error = None;
+        }
+    }
+}
+
+/// Container a stream is recorded to: MJPG goes into AVI as is, raw YUV and
+/// grey formats into Y4M.
+pub enum Writer {
+    Avi(AviWriter),
+    Y4m(Y4mWriter),
+}
+
+impl Writer {
+    /// Starts a "<prefix>-<timestamp>" file in `dir` for `params` frames:
+    /// Y4M for the raw formats it holds, MJPEG AVI for the rest.
+    pub fn create(dir: &Path, prefix: &str, params: &StreamParams) -> io::Result<(PathBuf, Writer)> {
+        let StreamParams { fourcc, width, height, stride, interval, y4m_420 } = *params;
+
+        let context = |path: &Path, er: io::Error| io::Error::new(er.kind(), format!("{}: {}", path.display(), er));
+
+        fs::create_dir_all(dir).map_err(|er| context(dir, er))?;
+
+        let (path, writer) = match Chroma::for_fourcc(&fourcc, y4m_420) {
+            Some(chroma) => {
+                let path = dir.join(snapshot::timestamped_name(prefix, "y4m"));
+                let writer = Y4mWriter::create(&path, fourcc, width, height, stride, interval, chroma)
+                    .map(Writer::Y4m);
+                (path, writer)
+            },
+            None => {
+                let path = dir.join(snapshot::timestamped_name(prefix, "avi"));
+                let writer = AviWriter::create(&path, width, height, interval).map(Writer::Avi);
+                (path, writer)
+            },
+        };
+
+        let writer = writer.map_err(|er| context(&path, er))?;
+        Ok((path, writer))
+    }
+
+    /// `create` for decoded frames like `frame`.
+    pub fn for_frame(dir: &Path, prefix: &str, frame: &Frame, interval: (u32, u32),
+        y4m_420: bool) -> io::Result<(PathBuf, Writer)> {
+        Self::create(dir, prefix, &StreamParams::of_frame(frame, interval, y4m_420))
+    }
+
+    /// Whether `fourcc` frames are stored as they come, MJPG in AVI and the
+    /// raw formats in Y4M, rather than having to be encoded.
+    pub fn stores_as_is(fourcc: &[u8; 4], y4m_420: bool) -> bool {
+        fourcc == b"MJPG" || Chroma::for_fourcc(fourcc, y4m_420).is_some()
+    }
+
+    pub fn write_frame(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            Writer::Avi(writer) => writer.write_frame(buf),
+            Writer::Y4m(writer) => writer.write_frame(buf),
+        }
+    }
+
+    /// Appends a decoded frame, encoding it to JPEG for AVI files unless the
+    /// camera sent one.
+    pub fn write_decoded(&mut self, frame: &Frame) -> io::Result<()> {
+        match self {
+            Writer::Avi(writer) => writer.write_frame(&snapshot::encode_jpeg(frame)?),
+            Writer::Y4m(writer) => writer.write_frame(&frame.data),
+        }
+    }
+
+    pub fn file_len(&self) -> u64 {
+        match self {
+            Writer::Avi(writer) => writer.file_len(),
+            Writer::Y4m(writer) => writer.file_len(),
+        }
+    }
+
+    pub fn frames(&self) -> usize {
+        match self {
+            Writer::Avi(writer) => writer.frames(),
+            Writer::Y4m(writer) => writer.frames(),
+        }
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Writer::Avi(writer) => writer.finish(),
+            Writer::Y4m(writer) => writer.finish(),
+        }
+    }
+}
+
+/// What a recording file was started with. Any change starts a new file,
+/// neither container can change format midway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamParams {
+    pub fourcc: [u8; 4],
+    pub width: u32,
+    pub height: u32,
+    pub stride: u32,
+    pub interval: (u32, u32),
+    /// Write packed 4:2:2 to Y4M as 4:2:0.
+    pub y4m_420: bool,
+}
+
+impl StreamParams {
+    pub fn of_frame(frame: &Frame, interval: (u32, u32), y4m_420: bool) -> Self {
+        Self {
+            fourcc: frame.fourcc,
+            width: frame.width,
+            height: frame.height,
+            stride: frame.stride,
+            interval,
+            y4m_420,
+        }
+    }
+}
+
+/// Writes the frames straight from the capture buffers. Lives on the capture
+/// thread, so recording is independent of the preview.
+pub struct Recorder {
+    writer: Option<Writer>,
+    params: Option<StreamParams>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self {
+            writer: None,
+            params: None,
+        }
+    }
+
+    /// Appends one frame as dequeued from the device, starting, splitting or
+    /// closing files as needed.
+    pub fn record(&mut self, shared: &Mutex<Recording>, fmt: &Format, interval: (u32, u32), buf: &[u8]) {
+        let (active, dir, split_size, y4m_420) = {
+            let recording = shared.lock().unwrap();
+            (recording.active, recording.dir.clone(), recording.split_size, recording.y4m_420)
+        };
+
+        if !active {
+            self.stop(shared);
+            return;
+        }
+
+        let fourcc = fmt.fourcc.repr;
+        let is_mjpg = &fourcc == b"MJPG";
+
+        if !Writer::stores_as_is(&fourcc, y4m_420) {
+            self.stop(shared);
+            let mut recording = shared.lock().unwrap();
+            recording.active = false;
+            recording.error = Some(format!("Can't record {}, only MJPG (AVI) and YUV or grey formats (Y4M)",
+                String::from_utf8_lossy(&fourcc)));
+            return;
+        }
+
+        // a truncated raw frame would shift every following one
+        if !is_mjpg && buf.len() < pixfmt::frame_len(&fourcc, fmt.stride as usize, fmt.height as usize) {
+            return;
+        }
+
+        let params = StreamParams {
+            fourcc,
+            width: fmt.width,
+            height: fmt.height,
+            stride: fmt.stride,
+            interval,
+            y4m_420,
+        };
+
+        if let Some(writer) = &self.writer {
+            // AVI 1.0 files have to stay small, Y4M has no such limit
+            let full = match writer {
+                Writer::Avi(writer) => writer.is_full(buf.len(), split_size),
+                Writer::Y4m(_) => false,
+            };
+
+            if self.params != Some(params) || full {
+                self.stop(shared);
+            }
+        }
+
+        if self.writer.is_none() {
+            if let Err(er) = self.start(shared, dir, params) {
+                self.fail(shared, er);
+                return;
+            }
+        }
+
+        let writer = self.writer.as_mut().unwrap();
+
+        if let Err(er) = writer.write_frame(buf) {
+            self.fail(shared, er.to_string());
+            return;
+        }
+
+        let mut recording = shared.lock().unwrap();
+        recording.frames = writer.frames() as u64;
+        recording.bytes = writer.file_len();
+    }
+
+    /// Closes the current file, if any.
+    pub fn stop(&mut self, shared: &Mutex<Recording>) {
+        let writer = match self.writer.take() {
+            Some(writer) => writer,
+            None => return,
+        };
+
+        let frames = writer.frames();
+
+        if let Err(er) = writer.finish() {
+            println!("Failed to finish recording: {}", er);
+            shared.lock().unwrap().error = Some(er.to_string());
+        }
+
+        let mut recording = shared.lock().unwrap();
+        if let Some(file) = recording.file.take() {
+            println!("recorded {} frames to {}", frames, file.display());
+        }
+    }
+
+    fn start(&mut self, shared: &Mutex<Recording>, dir: PathBuf, params: StreamParams) -> Result<(), String> {
+        let (path, writer) = Writer::create(&dir, "recording", &params).map_err(|er| er.to_string())?;
+
+        println!("recording to {}", path.display());
+
+        self.writer = Some(writer);
+        self.params = Some(params);
+
+        let mut recording = shared.lock().unwrap();
+        recording.file = Some(path);
+        recording.frames = 0;
+        recording.bytes = 0;
+
+        Ok(())
+    }
+
+    fn fail(&mut self, shared: &Mutex<Recording>, er: String) {
+        println!("Recording failed: {}", er);
+        self.stop(shared);
+
+        let mut recording = shared.lock().unwrap();
+        recording.active = false;
+        recording.error = Some(er);
+    }
+}
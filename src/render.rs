@@ -1,55 +1,65 @@
-use std::sync::mpsc;
-use std::time::SystemTime;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
-use sdl2::pixels::PixelFormatEnum;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
-use sdl2::event::Event;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
 
-pub fn from_bytes(input:&mut &[u8]) -> Vec<u32> {
-    
-    let mut len = input.len();
-
-    let mut output : Vec<u32> = Vec::new();
-
-    while len >= 4 {
-        let (int_bytes, rest) = input.split_at(std::mem::size_of::<u32>());
-        *input = rest;
-        output.push(u32::from_be_bytes(int_bytes.try_into().unwrap()));
-        len -= 4;
-    }
-
-    //println!("size: {:?}", output);
+use v4l::buffer::Flags as BufferFlags;
 
-    output
-}
+use crate::frame::FrameSlot;
+use crate::motion::{Motion, Roi};
+use crate::pixfmt;
+use crate::record::Recording;
+use crate::snapshot::Snapshot;
+use crate::state::{GuiState, WindowGeometry};
 
 pub struct Render {
     width: u32,
     height: u32,
     fourcc: [u8; 4],
+    snapshot: Arc<Mutex<Snapshot>>,
+    recording: Arc<Mutex<Recording>>,
+    motion: Arc<Mutex<Motion>>,
+    /// Where the preview window opens, and where it's remembered.
+    state: Arc<Mutex<GuiState>>,
 }
 
 impl Render {
-    pub fn new(width: u32, height: u32, fourcc: &[u8; 4]) -> Self {
+    pub fn new(width: u32, height: u32, fourcc: &[u8; 4], snapshot: Arc<Mutex<Snapshot>>,
+        recording: Arc<Mutex<Recording>>, motion: Arc<Mutex<Motion>>, state: Arc<Mutex<GuiState>>) -> Self {
         Self{
             width,
             height,
             fourcc: *fourcc,
+            snapshot,
+            recording,
+            motion,
+            state,
         }
     }
 
-    pub fn render_data(&mut self, rx : mpsc::Receiver<Vec<u8>>) -> Result<(), String> {
+    pub fn render_data(&mut self, frames: Arc<FrameSlot>) -> Result<(), String> {
         
         let mut fps_count : f64 = 0.;
         // We init systems.
         let sdl_context = sdl2::init().expect("failed to init SDL");
         let video_subsystem = sdl_context.video().expect("failed to get video context");
-        //we create a window
-        let window = video_subsystem
-            .window("Rustycamera", 800, 600)
-            .position_centered()
+        //we create a window, where it was left last time
+        let geometry = self.state.lock().unwrap().preview_window;
+        let (width, height) = geometry
+            .map(|geometry| (geometry.size.0 as u32, geometry.size.1 as u32))
+            .unwrap_or((800, 600));
+        let mut window = video_subsystem.window("Rustycamera", width, height);
+        match geometry.and_then(|geometry| geometry.position) {
+            Some((x, y)) => window.position(x as i32, y as i32),
+            None => window.position_centered(),
+        };
+        let window = window
             .resizable()
             .opengl()
             .build()
@@ -61,11 +71,8 @@ impl Render {
             .expect("failed to build window's canvas");
         let texture_creator = canvas.texture_creator();
         
-        let mut pix_fmt = match &self.fourcc {
-            b"YUYV" => PixelFormatEnum::YUY2,
-            b"MJPG" => PixelFormatEnum::RGBA32,
-            _ => panic!("invalid buffer pixelformat"),
-        };
+        // capture only delivers supported formats, start with anything valid
+        let mut pix_fmt = pixfmt::texture_format(&self.fourcc).unwrap_or(PixelFormatEnum::YUY2);
 
         let _ = canvas.set_logical_size(self.width, self.height);
         
@@ -73,6 +80,11 @@ impl Render {
         let mut texture = texture_creator.create_texture_streaming(pix_fmt, self.width, self.height).unwrap();
         
         let mut running = true;
+        let mut paused = false;
+        // corners of the motion region being drawn, in frame pixels
+        let mut drag: Option<((i32, i32), (i32, i32))> = None;
+        // a format without a texture, reported once
+        let mut unsupported: Option<[u8; 4]> = None;
         let mut event_pump = sdl_context.event_pump().unwrap();
         
         let mut now = SystemTime::now();
@@ -88,41 +100,111 @@ impl Render {
                     } => {
                         running = false;
                     }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::S),
+                        repeat: false,
+                        ..
+                    } => {
+                        self.snapshot.lock().unwrap().request();
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::R),
+                        repeat: false,
+                        ..
+                    } => {
+                        self.recording.lock().unwrap().toggle();
+                    }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Space),
+                        repeat: false,
+                        ..
+                    } => {
+                        paused = !paused;
+                    }
+                    // mouse positions arrive in frame pixels, the canvas has
+                    // the frame size as its logical size
+                    Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. }
+                        if self.motion.lock().unwrap().enabled => {
+                        drag = Some(((x, y), (x, y)));
+                    }
+                    Event::MouseMotion { x, y, .. } => {
+                        if let Some((_, end)) = &mut drag {
+                            *end = (x, y);
+                        }
+                    }
+                    Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } => {
+                        if let Some((start, end)) = drag.take() {
+                            let roi = self.roi(start, end);
+                            // a plain click isn't a region
+                            if roi.width > 0.01 && roi.height > 0.01 {
+                                self.motion.lock().unwrap().roi.push(roi);
+                            }
+                        }
+                    }
+                    Event::MouseButtonDown { mouse_btn: MouseButton::Right, .. } => {
+                        self.motion.lock().unwrap().roi.clear();
+                    }
+                    Event::Window { win_event: WindowEvent::Moved(..) | WindowEvent::SizeChanged(..), .. } => {
+                        let window = canvas.window();
+                        let ((x, y), (width, height)) = (window.position(), window.size());
+                        self.state.lock().unwrap().preview_window = Some(WindowGeometry {
+                            position: Some((x as f32, y as f32)),
+                            size: (width as f32, height as f32),
+                        });
+                    }
                     _ => {}
                 }
             }
 
-            let fourcc_data = rx.recv().unwrap();
-            
-            let size_data = rx.recv().unwrap();
-            let b: &mut &[u8] = &mut &size_data[..];
-            let size = from_bytes(b);
-        
-            let data = rx.recv().unwrap();
+            let frame = match frames.take_timeout(Duration::from_millis(100)) {
+                Some(frame) => frame,
+                None => continue,
+            };
+
+            // the driver flags frames it knows to be corrupted, don't show them
+            if frame.flags.contains(BufferFlags::ERROR) {
+                continue;
+            }
 
-            if size[0] != self.width || size[1] != self.height || !self.fourcc.eq(&fourcc_data[..]) {
-                self.width = size[0];
-                self.height = size[1];
-                self.fourcc.clone_from_slice(&fourcc_data);
+            // while paused frames are still taken, so the drop counter stays
+            // meaningful, but not shown. Recording happens on the capture thread.
+            if !paused {
+                if frame.width != self.width || frame.height != self.height || self.fourcc != frame.fourcc {
+                    // the texture and size only change once there is a
+                    // texture for the new format, until then its frames are skipped
+                    let new_fmt = match pixfmt::texture_format(&frame.fourcc) {
+                        Some(pix_fmt) => pix_fmt,
+                        None => {
+                            if unsupported != Some(frame.fourcc) {
+                                println!("no texture format for {}, skipping its frames", frame.fourcc_str());
+                                unsupported = Some(frame.fourcc);
+                            }
+                            continue;
+                        }
+                    };
 
-                println!("new render texture format: {} -> {}x{}", std::str::from_utf8(&self.fourcc).expect("Failed to convert fourcc to string"), self.width, self.height);
+                    texture = texture_creator.create_texture_streaming(new_fmt, frame.width, frame.height).unwrap();
+                    pix_fmt = new_fmt;
+                    unsupported = None;
 
-                pix_fmt = match &self.fourcc {
-                    b"YUYV" => PixelFormatEnum::YUY2,
-                    b"MJPG" => PixelFormatEnum::RGBA32,
-                    _ => panic!("invalid buffer pixelformat"),
-                };
+                    self.width = frame.width;
+                    self.height = frame.height;
+                    self.fourcc = frame.fourcc;
+                    let _ = canvas.set_logical_size(self.width, self.height);
 
-                texture = texture_creator.create_texture_streaming(pix_fmt, self.width, self.height).unwrap();
-                let _ = canvas.set_logical_size(self.width, self.height);
+                    println!("new render texture format: {} ({:?}) -> {}x{} (frame {} at {})",
+                        frame.fourcc_str(), pix_fmt, self.width, self.height, frame.sequence, frame.timestamp);
+                }
+
+                texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+                    pixfmt::upload(&frame, buffer, pitch);
+                }).expect("Failed texture data copy");
             }
 
-            texture.with_lock(None, |buffer: &mut [u8], _pitch: usize| {
-                buffer[..].clone_from_slice(&data);
-            }).expect("Failed texture data copy");
-        
+            canvas.set_draw_color(Color::RGB(0, 0, 0));
             canvas.clear();
             canvas.copy(&texture, None, None).expect("copy texture");
+            self.draw_motion(&mut canvas, drag);
             canvas.present();
 
             fps_count += 1.;
@@ -132,7 +214,16 @@ impl Render {
                     if elapsed.as_secs_f64() >= 2.0 {
                         let fps = fps_count / elapsed.as_secs_f64();
                         let window = canvas.window_mut();
-                        let title = format!("rustycamera  - {:.2} fps", fps);
+                        let mut title = format!("rustycamera  - {:.2} fps - {} dropped", fps, frames.dropped());
+                        if paused {
+                            title.push_str(" - paused");
+                        }
+                        if self.recording.lock().unwrap().active {
+                            title.push_str(" - REC");
+                        }
+                        if self.motion.lock().unwrap().active {
+                            title.push_str(" - MOTION");
+                        }
                         let _ = window.set_title(&title);
                         fps_count = 0.;
                         now = SystemTime::now();
@@ -147,4 +238,36 @@ impl Render {
     
         Ok(())
     }
+
+    /// Motion regions in yellow and what moved in red, while the detector
+    /// is on.
+    fn draw_motion(&self, canvas: &mut Canvas<Window>, drag: Option<((i32, i32), (i32, i32))>) {
+        let motion = self.motion.lock().unwrap();
+        if !motion.enabled {
+            return;
+        }
+
+        let (width, height) = (self.width as f32, self.height as f32);
+
+        canvas.set_draw_color(Color::RGB(250, 200, 0));
+        let drawing = drag.map(|(start, end)| self.roi(start, end));
+        for roi in motion.roi.iter().chain(&drawing) {
+            let _ = canvas.draw_rect(Rect::new((roi.x * width) as i32, (roi.y * height) as i32,
+                (roi.width * width).max(1.) as u32, (roi.height * height).max(1.) as u32));
+        }
+
+        canvas.set_draw_color(Color::RGB(255, 40, 40));
+        for blob in &motion.blobs {
+            let _ = canvas.draw_rect(Rect::new(blob.x as i32, blob.y as i32, blob.width, blob.height));
+        }
+    }
+
+    /// The region between two corners in frame pixels.
+    fn roi(&self, start: (i32, i32), end: (i32, i32)) -> Roi {
+        let scale = |(x, y): (i32, i32)| (
+            (x as f32 / self.width as f32).clamp(0., 1.),
+            (y as f32 / self.height as f32).clamp(0., 1.),
+        );
+        Roi::from_corners(scale(start), scale(end))
+    }
 }
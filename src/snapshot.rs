@@ -0,0 +1,199 @@
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::{ExtendedColorType, ImageEncoder};
+
+use crate::frame::Frame;
+use crate::pixfmt;
+
+const JPEG_QUALITY: u8 = 90;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Ppm,
+}
+
+impl ImageFormat {
+    pub const ALL: [ImageFormat; 3] = [ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::Ppm];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "PNG",
+            ImageFormat::Jpeg => "JPEG",
+            ImageFormat::Ppm => "PPM",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Ppm => "ppm",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+            "ppm" => Some(ImageFormat::Ppm),
+            _ => None,
+        }
+    }
+}
+
+/// Snapshot settings shared by the GUI and the preview window. Either side
+/// asks for a snapshot with `request`; the capture thread saves the next
+/// frame and leaves the outcome in `last`, whether a preview is open or not.
+pub struct Snapshot {
+    pub dir: PathBuf,
+    pub format: ImageFormat,
+    pub last: Option<Result<PathBuf, String>>,
+    requested: bool,
+}
+
+impl Snapshot {
+    pub fn new(dir: PathBuf, format: ImageFormat) -> Self {
+        Self {
+            dir,
+            format,
+            last: None,
+            requested: false,
+        }
+    }
+
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    pub fn take_request(&mut self) -> bool {
+        std::mem::take(&mut self.requested)
+    }
+}
+
+/// Saves `frame` if a snapshot was asked for. Encoding happens on its own
+/// thread so capture doesn't stall.
+pub fn save_requested(snapshot: &Arc<Mutex<Snapshot>>, frame: &Frame) {
+    let (dir, format) = {
+        let mut snapshot = snapshot.lock().unwrap();
+        if !snapshot.take_request() {
+            return;
+        }
+        (snapshot.dir.clone(), snapshot.format)
+    };
+
+    let frame = frame.clone();
+    let snapshot = snapshot.clone();
+
+    thread::spawn(move || {
+        let result = save(&frame, &dir, format);
+
+        match &result {
+            Ok(path) => println!("saved snapshot {}", path.display()),
+            Err(er) => println!("Failed to save snapshot: {}", er),
+        }
+
+        snapshot.lock().unwrap().last = Some(result.map_err(|er| er.to_string()));
+    });
+}
+
+/// Writes `frame` to `dir` under a timestamped name. MJPG frames saved as
+/// JPEG are written as the original bytes from the camera, everything else is
+/// converted to RGB and encoded.
+pub fn save(frame: &Frame, dir: &Path, format: ImageFormat) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(timestamped_name("snapshot", format.extension()));
+
+    write_image(frame, &path, format)?;
+
+    Ok(path)
+}
+
+/// Writes `frame` to `path`, see `save`.
+pub fn write_image(frame: &Frame, path: &Path, format: ImageFormat) -> io::Result<()> {
+    if let (ImageFormat::Jpeg, Some(jpeg)) = (format, &frame.jpeg) {
+        return fs::write(path, jpeg);
+    }
+
+    let rgb = pixfmt::to_rgb(frame).ok_or(io::Error::new(io::ErrorKind::InvalidData,
+        format!("can't convert {} frames", frame.fourcc_str())))?;
+
+    let mut file = BufWriter::new(fs::File::create(path)?);
+
+    match format {
+        ImageFormat::Png => PngEncoder::new(&mut file)
+            .write_image(&rgb, frame.width, frame.height, ExtendedColorType::Rgb8)
+            .map_err(io::Error::other)?,
+        ImageFormat::Jpeg => JpegEncoder::new_with_quality(&mut file, JPEG_QUALITY)
+            .encode(&rgb, frame.width, frame.height, ExtendedColorType::Rgb8)
+            .map_err(io::Error::other)?,
+        ImageFormat::Ppm => {
+            write!(file, "P6\n{} {}\n255\n", frame.width, frame.height)?;
+            file.write_all(&rgb)?;
+        },
+    }
+
+    file.flush()
+}
+
+/// The frame as JPEG: the original bytes for MJPG, encoded otherwise.
+pub fn encode_jpeg(frame: &Frame) -> io::Result<Vec<u8>> {
+    if let Some(jpeg) = &frame.jpeg {
+        return Ok(jpeg.clone());
+    }
+
+    let rgb = pixfmt::to_rgb(frame).ok_or(io::Error::new(io::ErrorKind::InvalidData,
+        format!("can't convert {} frames", frame.fourcc_str())))?;
+
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY)
+        .encode(&rgb, frame.width, frame.height, ExtendedColorType::Rgb8)
+        .map_err(io::Error::other)?;
+
+    Ok(jpeg)
+}
+
+/// "<prefix>-<timestamp>.<extension>", see `timestamp`.
+pub fn timestamped_name(prefix: &str, extension: &str) -> String {
+    format!("{}-{}.{}", prefix, timestamp(), extension)
+}
+
+/// "YYYYMMDD-HHMMSS-mmm" from the current UTC time.
+pub fn timestamp() -> String {
+    timestamp_at(SystemTime::now())
+}
+
+/// `timestamp` for any point in time.
+pub fn timestamp_at(time: SystemTime) -> String {
+    let now = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = now.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let time = secs % 86400;
+
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}",
+        year, month, day, time / 3600, time / 60 % 60, time % 60, now.subsec_millis())
+}
+
+// days since 1970-01-01 to a proleptic Gregorian date, after Howard Hinnant's
+// civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
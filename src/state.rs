@@ -0,0 +1,84 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::devices::DeviceInfo;
+use crate::profile;
+
+/// Where a window was and how big it was, in logical pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    /// `None` where windows can't tell their position (Wayland).
+    pub position: Option<(f32, f32)>,
+    pub size: (f32, f32),
+}
+
+/// The format, size and rate the last device was captured with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedFormat {
+    pub fourcc: String,
+    pub width: u32,
+    pub height: u32,
+    pub interval: (u32, u32),
+}
+
+/// How the windows were left last time, restored on the next start.
+/// Anything missing from the file keeps its default.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuiState {
+    pub theme: String,
+    pub tab: u32,
+    pub ui_scale: f32,
+    pub gui_window: Option<WindowGeometry>,
+    pub preview_window: Option<WindowGeometry>,
+    /// Width of the embedded preview panel.
+    pub preview_width: f32,
+    pub device: Option<DeviceInfo>,
+    pub format: Option<SavedFormat>,
+}
+
+impl Default for GuiState {
+    fn default() -> Self {
+        Self {
+            theme: String::from("Mocha"),
+            tab: 0,
+            ui_scale: 1.25,
+            gui_window: None,
+            preview_window: None,
+            preview_width: 640.0,
+            device: None,
+            format: None,
+        }
+    }
+}
+
+impl GuiState {
+    /// Reads `path`. A missing or broken file just means starting fresh.
+    pub fn load(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|er| {
+                println!("Ignoring {}: {}", path.display(), er);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let error = |er: &dyn std::fmt::Display| format!("{}: {}", path.display(), er);
+
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|er| error(&er))?;
+        }
+
+        let text = serde_json::to_string_pretty(self).map_err(|er| error(&er))?;
+        fs::write(path, text + "\n").map_err(|er| error(&er))
+    }
+}
+
+/// `$XDG_CONFIG_HOME/rustycamera/state.json`, or the same under ~/.config.
+pub fn default_path() -> PathBuf {
+    profile::config_dir().join("state.json")
+}
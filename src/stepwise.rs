@@ -0,0 +1,184 @@
+use v4l::framesize;
+use v4l::frameinterval;
+
+// v4l2_frmsizetypes / v4l2_frmivaltypes
+const TYPE_CONTINUOUS: u32 = 2;
+
+/// Frame sizes reported as a min/max/step range instead of a list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeRange {
+    pub min: (u32, u32),
+    pub max: (u32, u32),
+    pub step: (u32, u32),
+}
+
+impl SizeRange {
+    pub fn new(stepwise: &framesize::Stepwise, typ: u32) -> Self {
+        let step = if typ == TYPE_CONTINUOUS {
+            (1, 1)
+        } else {
+            (stepwise.step_width.max(1), stepwise.step_height.max(1))
+        };
+
+        Self {
+            min: (stepwise.min_width, stepwise.min_height),
+            max: (stepwise.max_width, stepwise.max_height),
+            step,
+        }
+    }
+
+    /// Clamps the size to the range and rounds it to the nearest step.
+    pub fn snap(&self, width: u32, height: u32) -> (u32, u32) {
+        (snap_u32(width, self.min.0, self.max.0, self.step.0),
+            snap_u32(height, self.min.1, self.max.1, self.step.1))
+    }
+}
+
+fn snap_u32(value: u32, min: u32, max: u32, step: u32) -> u32 {
+    snap(value as i64, min as i64, max as i64, step as i64) as u32
+}
+
+/// Clamps `value` to `min..=max` and rounds it to the nearest `min` plus a
+/// multiple of `step`. Frame sizes and control values both snap this way.
+pub fn snap(value: i64, min: i64, max: i64, step: i64) -> i64 {
+    let max = max.max(min);
+    let step = step.max(1);
+    let steps = (value.clamp(min, max) - min + step / 2) / step;
+    let snapped = min + steps * step;
+
+    // rounding up may overshoot a max that isn't on the step grid
+    if snapped > max {
+        snapped - step
+    } else {
+        snapped
+    }
+}
+
+/// Frame intervals reported as a min/max/step range, all in seconds as
+/// numerator/denominator pairs like the rest of the app uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntervalRange {
+    pub min: (u32, u32),
+    pub max: (u32, u32),
+    /// `None` for continuous ranges.
+    pub step: Option<(u32, u32)>,
+}
+
+impl IntervalRange {
+    pub fn new(stepwise: &frameinterval::Stepwise, typ: u32) -> Self {
+        let step = (stepwise.step.numerator, stepwise.step.denominator);
+        let continuous = typ == TYPE_CONTINUOUS || step.0 == 0 || step.1 == 0;
+
+        Self {
+            min: (stepwise.min.numerator, stepwise.min.denominator),
+            max: (stepwise.max.numerator, stepwise.max.denominator),
+            step: if continuous { None } else { Some(step) },
+        }
+    }
+
+    /// Lowest and highest frame rate in the range.
+    pub fn fps_bounds(&self) -> (f64, f64) {
+        (1.0 / seconds(self.max), 1.0 / seconds(self.min))
+    }
+
+    /// Turns a frame rate into the nearest interval the driver accepts.
+    pub fn snap_fps(&self, fps: f64) -> (u32, u32) {
+        let (min_fps, max_fps) = self.fps_bounds();
+        let fps = fps.clamp(min_fps, max_fps);
+
+        match self.step {
+            Some((step_num, step_denom)) => {
+                // interval = min + k * step, on a common denominator
+                let (min_num, min_denom) = (self.min.0 as u64, self.min.1 as u64);
+                let (step_num, step_denom) = (step_num as u64, step_denom as u64);
+
+                let span = seconds(self.max) - seconds(self.min);
+                let max_k = (span / seconds((step_num as u32, step_denom as u32))).floor();
+                let k = ((1.0 / fps - seconds(self.min)) / seconds((step_num as u32, step_denom as u32)))
+                    .round()
+                    .clamp(0.0, max_k) as u64;
+
+                reduce(min_num * step_denom + k * step_num * min_denom, min_denom * step_denom)
+            },
+            None => reduce(1000, (fps * 1000.0).round() as u64),
+        }
+    }
+}
+
+pub fn seconds(interval: (u32, u32)) -> f64 {
+    interval.0 as f64 / interval.1 as f64
+}
+
+fn reduce(num: u64, denom: u64) -> (u32, u32) {
+    let (mut a, mut b) = (num, denom);
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    let gcd = a.max(1);
+    let (mut num, mut denom) = (num / gcd, denom / gcd);
+
+    // keep it representable in the v4l2 u32 fields
+    while num > u32::MAX as u64 || denom > u32::MAX as u64 {
+        num /= 2;
+        denom /= 2;
+    }
+
+    (num as u32, denom.max(1) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snaps_to_the_grid() {
+        assert_eq!(snap(7, 0, 100, 5), 5);
+        assert_eq!(snap(8, 0, 100, 5), 10);
+        assert_eq!(snap(-20, -10, 10, 4), -10);
+        assert_eq!(snap(200, 0, 100, 5), 100);
+        assert_eq!(snap(3, 1, 9, 0), 3);
+        assert_eq!(snap(5, 10, 0, 1), 10);
+    }
+
+    #[test]
+    fn stays_on_the_grid_below_an_off_grid_maximum() {
+        // 0, 4, 8 are valid; 10 is the maximum but not a step
+        assert_eq!(snap(10, 0, 10, 4), 8);
+        assert_eq!(snap(9, 0, 10, 4), 8);
+        assert_eq!(snap_u32(u32::MAX, 16, 1930, 16), 1920);
+    }
+
+    #[test]
+    fn sizes() {
+        let range = SizeRange { min: (16, 16), max: (1920, 1080), step: (16, 8) };
+        assert_eq!(range.snap(1000, 500), (1008, 504));
+        assert_eq!(range.snap(4000, 0), (1920, 16));
+    }
+
+    #[test]
+    fn stepped_intervals() {
+        // 1/30 s to 1 s in steps of 1/30 s
+        let range = IntervalRange { min: (1, 30), max: (1, 1), step: Some((1, 30)) };
+        assert_eq!(range.fps_bounds(), (1.0, 30.0));
+        assert_eq!(range.snap_fps(15.0), (1, 15));
+        assert_eq!(range.snap_fps(10.0), (1, 10));
+        assert_eq!(range.snap_fps(100.0), (1, 30));
+        assert_eq!(range.snap_fps(0.1), (1, 1));
+    }
+
+    #[test]
+    fn continuous_intervals() {
+        let range = IntervalRange { min: (1, 60), max: (1, 5), step: None };
+        assert_eq!(range.snap_fps(25.0), (1, 25));
+        assert_eq!(range.snap_fps(29.97), (100, 2997));
+        assert_eq!(range.snap_fps(1.0), (1, 5));
+    }
+
+    #[test]
+    fn reduces_fractions() {
+        assert_eq!(reduce(60, 900), (1, 15));
+        assert_eq!(reduce(7, 0), (1, 1));
+        // too big for v4l2, halved until it fits
+        assert_eq!(reduce(6_000_000_000, 3_000_000_001), (3_000_000_000, 1_500_000_000));
+    }
+}
@@ -0,0 +1,368 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::avi::AviWriter;
+use crate::frame::Frame;
+use crate::record::Recording;
+use crate::snapshot::{self, ImageFormat};
+
+/// How long the stream runs before a shot after being stopped, so auto
+/// exposure and white balance have settled.
+pub const WARMUP: Duration = Duration::from_secs(2);
+
+/// Intervals shorter than this keep the stream running even when stopping
+/// between shots is asked for; restarting costs more than it saves.
+const MIN_IDLE: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimelapseOutput {
+    /// Numbered images in a directory of their own.
+    Images,
+    /// An MJPEG AVI played back at `playback_fps`, continued in numbered
+    /// files past the recording split size.
+    Avi,
+}
+
+/// Timelapse schedule and status, shared by the GUI and the capture thread.
+/// Setting `running` starts the schedule, the capture thread clears it once
+/// the count or end time is reached.
+pub struct Timelapse {
+    pub interval: Duration,
+    /// First shot, `None` to start right away.
+    pub start: Option<SystemTime>,
+    /// Stop after this many shots.
+    pub count: Option<u32>,
+    /// Stop at this time.
+    pub end: Option<SystemTime>,
+    pub output: TimelapseOutput,
+    pub image_format: ImageFormat,
+    pub playback_fps: u32,
+    /// Stop streaming between shots, for long intervals.
+    pub stop_between: bool,
+    pub running: bool,
+    pub taken: u32,
+    pub next: Option<SystemTime>,
+    pub path: Option<PathBuf>,
+    pub error: Option<String>,
+}
+
+impl Timelapse {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            start: None,
+            count: None,
+            end: None,
+            output: TimelapseOutput::Images,
+            image_format: ImageFormat::Png,
+            playback_fps: 25,
+            stop_between: false,
+            running: false,
+            taken: 0,
+            next: None,
+            path: None,
+            error: None,
+        }
+    }
+}
+
+/// Takes the shots on the capture thread. Shots are scheduled on a fixed grid
+/// from the start time, so they don't drift with the frame rate.
+pub struct TimelapseRunner {
+    next: Option<SystemTime>,
+    taken: u32,
+    dir: PathBuf,
+    avi: Option<AviWriter>,
+    /// Path of the AVI without extension, and which part is being written.
+    avi_base: PathBuf,
+    avi_part: u32,
+}
+
+impl TimelapseRunner {
+    pub fn new() -> Self {
+        Self {
+            next: None,
+            taken: 0,
+            dir: PathBuf::new(),
+            avi: None,
+            avi_base: PathBuf::new(),
+            avi_part: 1,
+        }
+    }
+
+    /// Looks at every decoded frame and keeps the ones that are due.
+    pub fn frame(&mut self, shared: &Mutex<Timelapse>, recording: &Mutex<Recording>, frame: &Frame) {
+        let mut timelapse = shared.lock().unwrap();
+
+        if !timelapse.running {
+            self.finish(&mut timelapse);
+            return;
+        }
+
+        let now = SystemTime::now();
+
+        if self.next.is_none() {
+            let dir = recording.lock().unwrap().dir.clone();
+            if let Err(er) = self.begin(&mut timelapse, dir, frame) {
+                println!("Failed to start timelapse: {}", er);
+                timelapse.error = Some(er);
+                timelapse.running = false;
+                return;
+            }
+            self.next = Some(timelapse.start.unwrap_or(now));
+        }
+
+        let next = self.next.unwrap();
+
+        let over = timelapse.count.is_some_and(|count| self.taken >= count)
+            || timelapse.end.is_some_and(|end| now >= end);
+        if over {
+            timelapse.running = false;
+            self.finish(&mut timelapse);
+            return;
+        }
+
+        if now < next {
+            timelapse.next = Some(next);
+            return;
+        }
+
+        let split_size = recording.lock().unwrap().split_size;
+        if let Err(er) = self.shoot(&mut timelapse, frame, split_size) {
+            println!("Timelapse shot failed: {}", er);
+            timelapse.error = Some(er);
+        }
+
+        self.taken += 1;
+        timelapse.taken = self.taken;
+
+        // skip the shots missed while the device was away
+        let interval = timelapse.interval.max(Duration::from_millis(1));
+        let mut next = next + interval;
+        while next <= now {
+            next += interval;
+        }
+        self.next = Some(next);
+        timelapse.next = Some(next);
+
+        let done = timelapse.count.is_some_and(|count| self.taken >= count)
+            || timelapse.end.is_some_and(|end| next > end);
+        if done {
+            timelapse.running = false;
+            self.finish(&mut timelapse);
+        }
+    }
+
+    /// When the stream can be stopped until, if the schedule allows for it.
+    pub fn idle_until(&self, shared: &Mutex<Timelapse>) -> Option<SystemTime> {
+        let timelapse = shared.lock().unwrap();
+        let next = self.next?;
+
+        if !timelapse.running || !timelapse.stop_between || timelapse.interval < MIN_IDLE {
+            return None;
+        }
+
+        let wake = next.checked_sub(WARMUP)?;
+        (wake > SystemTime::now()).then_some(wake)
+    }
+
+    pub fn stop(&mut self, shared: &Mutex<Timelapse>) {
+        self.finish(&mut shared.lock().unwrap());
+    }
+
+    fn begin(&mut self, timelapse: &mut Timelapse, dir: PathBuf, frame: &Frame) -> Result<(), String> {
+        let name = format!("timelapse-{}", snapshot::timestamp());
+
+        let path = match timelapse.output {
+            TimelapseOutput::Images => {
+                let path = dir.join(name);
+                fs::create_dir_all(&path).map_err(|er| format!("{}: {}", path.display(), er))?;
+                self.dir = path.clone();
+                path
+            },
+            TimelapseOutput::Avi => {
+                fs::create_dir_all(&dir).map_err(|er| format!("{}: {}", dir.display(), er))?;
+                self.avi_base = dir.join(name);
+                self.avi_part = 1;
+                self.open_avi(timelapse, frame)?
+            },
+        };
+
+        println!("timelapse to {}", path.display());
+
+        self.taken = 0;
+        timelapse.taken = 0;
+        timelapse.error = None;
+        timelapse.path = Some(path);
+
+        Ok(())
+    }
+
+    /// Starts part `avi_part` of the AVI.
+    fn open_avi(&mut self, timelapse: &Timelapse, frame: &Frame) -> Result<PathBuf, String> {
+        let path = match self.avi_part {
+            1 => PathBuf::from(format!("{}.avi", self.avi_base.display())),
+            part => PathBuf::from(format!("{}-{}.avi", self.avi_base.display(), part)),
+        };
+
+        let avi = AviWriter::create(&path, frame.width, frame.height, (1, timelapse.playback_fps.max(1)))
+            .map_err(|er| format!("{}: {}", path.display(), er))?;
+        self.avi = Some(avi);
+
+        Ok(path)
+    }
+
+    fn shoot(&mut self, timelapse: &mut Timelapse, frame: &Frame, split_size: u64) -> Result<(), String> {
+        match timelapse.output {
+            TimelapseOutput::Avi => {
+                let jpeg = snapshot::encode_jpeg(frame).map_err(|er| er.to_string())?;
+
+                // AVI 1.0 files have to stay small, split like recordings.
+                // A part that failed to open is tried again with the next shot.
+                let full = self.avi.as_ref().is_none_or(|avi| avi.is_full(jpeg.len(), split_size));
+                if full {
+                    if let Some(avi) = self.avi.take() {
+                        avi.finish().map_err(|er| er.to_string())?;
+                    }
+                    self.avi_part += 1;
+                    let path = self.open_avi(timelapse, frame)?;
+                    println!("timelapse continues in {}", path.display());
+                    timelapse.path = Some(path);
+                }
+
+                match &mut self.avi {
+                    Some(avi) => avi.write_frame(&jpeg).map_err(|er| er.to_string()),
+                    None => Ok(()),
+                }
+            },
+            TimelapseOutput::Images => {
+                let format = timelapse.image_format;
+                let path = self.dir.join(format!("frame-{:06}.{}", self.taken, format.extension()));
+                let frame = frame.clone();
+
+                // encoding can take a moment, the capture loop shouldn't wait
+                thread::spawn(move || {
+                    if let Err(er) = snapshot::write_image(&frame, &path, format) {
+                        println!("Failed to write {}: {}", path.display(), er);
+                    }
+                });
+
+                Ok(())
+            },
+        }
+    }
+
+    fn finish(&mut self, timelapse: &mut Timelapse) {
+        if self.next.take().is_none() {
+            return;
+        }
+
+        if let Some(avi) = self.avi.take() {
+            if let Err(er) = avi.finish() {
+                println!("Failed to finish timelapse: {}", er);
+                timelapse.error = Some(er.to_string());
+            }
+        }
+
+        println!("timelapse done after {} shots", self.taken);
+        timelapse.next = None;
+    }
+}
+
+fn local_offset(time: SystemTime) -> i64 {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+
+    // localtime_r is thread safe and reads the zone from TZ or /etc/localtime
+    if unsafe { libc::localtime_r(&secs, &mut tm) }.is_null() {
+        0
+    } else {
+        tm.tm_gmtoff
+    }
+}
+
+/// Next time the local clock shows "HH:MM", today or tomorrow.
+pub fn parse_clock(s: &str) -> Option<SystemTime> {
+    let (hours, minutes) = s.split_once(':')?;
+    let (hours, minutes) = (hours.trim().parse::<u64>().ok()?, minutes.trim().parse::<u64>().ok()?);
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+
+    let now = SystemTime::now();
+    let local = now.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64 + local_offset(now);
+    let midnight = local - local.rem_euclid(86400);
+
+    let mut at = midnight + (hours * 3600 + minutes * 60) as i64;
+    if at <= local {
+        at += 86400;
+    }
+
+    Some(UNIX_EPOCH + Duration::from_secs((at - local_offset(now)) as u64))
+}
+
+/// "HH:MM:SS" on the local clock.
+pub fn format_clock(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64 + local_offset(time);
+    let day = secs.rem_euclid(86400);
+
+    format!("{:02}:{:02}:{:02}", day / 3600, day / 60 % 60, day % 60)
+}
+
+/// "90", "90s", "5m" or "2h".
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let (number, unit) = match s.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(pos) => s.split_at(pos),
+        None => (s, "s"),
+    };
+
+    let scale = match unit {
+        "s" => 1.0,
+        "m" | "min" => 60.0,
+        "h" => 3600.0,
+        _ => return None,
+    };
+
+    let value = number.parse::<f64>().ok().filter(|value| *value > 0.0)?;
+    Duration::try_from_secs_f64(value * scale).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("5m"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_duration("5min"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("0.5s"), Some(Duration::from_millis(500)));
+
+        assert_eq!(parse_duration("0"), None);
+        assert_eq!(parse_duration("-5m"), None);
+        assert_eq!(parse_duration("5d"), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("99999999999999999999h"), None);
+    }
+
+    #[test]
+    fn clock_times() {
+        let now = SystemTime::now();
+        let at = parse_clock("07:30").unwrap();
+        let ahead = at.duration_since(now).unwrap();
+
+        assert!(ahead > Duration::ZERO && ahead <= Duration::from_secs(86400), "{:?}", ahead);
+        assert_eq!(format_clock(at), "07:30:00");
+        assert_eq!(format_clock(parse_clock(" 0:05 ").unwrap()), "00:05:00");
+
+        assert_eq!(parse_clock("24:00"), None);
+        assert_eq!(parse_clock("12:60"), None);
+        assert_eq!(parse_clock("1230"), None);
+        assert_eq!(parse_clock("aa:bb"), None);
+    }
+}
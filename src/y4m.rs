@@ -0,0 +1,319 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Chroma layout of a YUV4MPEG2 stream, the `C` header tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chroma {
+    C420,
+    /// 4:2:0 with chroma sited at the left sample of each pair, as packed
+    /// 4:2:2 gives when its rows are averaged.
+    C420Mpeg2,
+    C422,
+    Mono,
+    /// 16 bit little endian luma, an ffmpeg extension.
+    Mono16,
+}
+
+impl Chroma {
+    /// Layout a `fourcc` frame is written with, `None` for formats that
+    /// can't be stored without converting colour spaces. Packed 4:2:2 is
+    /// downsampled to 4:2:0 when `to_420` is set.
+    pub fn for_fourcc(fourcc: &[u8; 4], to_420: bool) -> Option<Chroma> {
+        match fourcc {
+            b"YUYV" | b"YVYU" | b"UYVY" if to_420 => Some(Chroma::C420Mpeg2),
+            b"YUYV" | b"YVYU" | b"UYVY" => Some(Chroma::C422),
+            b"NV12" | b"NV21" | b"YU12" | b"YV12" => Some(Chroma::C420),
+            b"GREY" => Some(Chroma::Mono),
+            b"Y16 " => Some(Chroma::Mono16),
+            _ => None,
+        }
+    }
+
+    fn tag(&self) -> &'static str {
+        match self {
+            Chroma::C420 => "420jpeg",
+            Chroma::C420Mpeg2 => "420mpeg2",
+            Chroma::C422 => "422",
+            Chroma::Mono => "mono",
+            Chroma::Mono16 => "mono16",
+        }
+    }
+}
+
+/// Writes uncompressed frames into a .y4m file, converting the capture
+/// layouts to the planar ones YUV4MPEG2 uses.
+pub struct Y4mWriter {
+    file: BufWriter<File>,
+    fourcc: [u8; 4],
+    width: usize,
+    height: usize,
+    stride: usize,
+    chroma: Chroma,
+    frames: usize,
+    len: u64,
+    // reused between frames
+    planes: [Vec<u8>; 3],
+}
+
+impl Y4mWriter {
+    /// Creates the file at `path` for `fourcc` frames with rows `stride` bytes
+    /// apart, one every `interval` (numerator/denominator) seconds.
+    pub fn create(path: &Path, fourcc: [u8; 4], width: u32, height: u32, stride: u32,
+        interval: (u32, u32), chroma: Chroma) -> io::Result<Self> {
+
+        let header = format!("YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C{}\n",
+            width, height, interval.1.max(1), interval.0.max(1), chroma.tag());
+
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(header.as_bytes())?;
+
+        Ok(Self {
+            file,
+            fourcc,
+            width: width as usize,
+            height: height as usize,
+            stride: stride as usize,
+            chroma,
+            frames: 0,
+            len: header.len() as u64,
+            planes: [Vec::new(), Vec::new(), Vec::new()],
+        })
+    }
+
+    /// Appends one frame, `buf` must hold at least a full frame.
+    pub fn write_frame(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.split_planes(buf);
+
+        self.file.write_all(b"FRAME\n")?;
+        self.len += 6;
+
+        for plane in &self.planes {
+            self.file.write_all(plane)?;
+            self.len += plane.len() as u64;
+        }
+
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    pub fn file_len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    fn split_planes(&mut self, buf: &[u8]) {
+        let (width, height, stride) = (self.width, self.height, self.stride);
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+        let [y, u, v] = &mut self.planes;
+
+        y.clear();
+        u.clear();
+        v.clear();
+
+        let rows = || buf.chunks(stride).take(height);
+
+        match &self.fourcc {
+            b"YUYV" | b"YVYU" | b"UYVY" => {
+                // byte offsets of the first luma, U and V sample in each pixel pair
+                let (luma, cb, cr) = match &self.fourcc {
+                    b"YUYV" => (0, 1, 3),
+                    b"YVYU" => (0, 3, 1),
+                    _ => (1, 0, 2),
+                };
+
+                for row in rows() {
+                    let start = y.len();
+                    // odd widths come in whole pairs, unless the stride cuts
+                    // off the last one; its missing chroma repeats the pair before
+                    for pair in row[..(chroma_width * 4).min(row.len())].chunks(4) {
+                        y.extend(pair.get(luma));
+                        y.extend(pair.get(luma + 2));
+                        let (last_u, last_v) = (u.last().copied(), v.last().copied());
+                        u.push(pair.get(cb).copied().or(last_u).unwrap_or(128));
+                        v.push(pair.get(cr).copied().or(last_v).unwrap_or(128));
+                    }
+                    y.resize(start + width, 0);
+                }
+
+                if self.chroma == Chroma::C420Mpeg2 {
+                    vertical_downsample(u, chroma_width, height);
+                    vertical_downsample(v, chroma_width, height);
+                }
+            },
+            b"NV12" | b"NV21" => {
+                for row in rows() {
+                    y.extend_from_slice(&row[..width]);
+                }
+
+                let interleaved = buf[stride * height..].chunks(stride).take(chroma_height);
+                for row in interleaved {
+                    for pair in row[..chroma_width * 2].chunks_exact(2) {
+                        u.push(pair[0]);
+                        v.push(pair[1]);
+                    }
+                }
+
+                if &self.fourcc == b"NV21" {
+                    std::mem::swap(u, v);
+                }
+            },
+            b"YU12" | b"YV12" => {
+                for row in rows() {
+                    y.extend_from_slice(&row[..width]);
+                }
+
+                let chroma_stride = stride.div_ceil(2);
+                let first = stride * height;
+                let second = first + chroma_stride * chroma_height;
+
+                for (plane, offset) in [(&mut *u, first), (&mut *v, second)] {
+                    for row in buf[offset..].chunks(chroma_stride).take(chroma_height) {
+                        plane.extend_from_slice(&row[..chroma_width]);
+                    }
+                }
+
+                if &self.fourcc == b"YV12" {
+                    std::mem::swap(u, v);
+                }
+            },
+            b"GREY" => {
+                for row in rows() {
+                    y.extend_from_slice(&row[..width]);
+                }
+            },
+            b"Y16 " => {
+                for row in rows() {
+                    y.extend_from_slice(&row[..width * 2]);
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+/// Averages each pair of rows of a `width` wide plane, turning 4:2:2 chroma
+/// into 4:2:0.
+fn vertical_downsample(plane: &mut Vec<u8>, width: usize, height: usize) {
+    let mut out = Vec::with_capacity(width * height.div_ceil(2));
+
+    for pair in plane.chunks(width * 2) {
+        let (top, bottom) = pair.split_at(width.min(pair.len()));
+        let bottom = if bottom.is_empty() { top } else { bottom };
+
+        out.extend(top.iter().zip(bottom).map(|(&a, &b)| (a as u16 + b as u16).div_ceil(2) as u8));
+    }
+
+    *plane = out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `buf` as one frame and returns the header line and the frame
+    /// planes that follow the FRAME marker.
+    fn write(name: &str, fourcc: &[u8; 4], width: u32, height: u32, stride: u32, chroma: Chroma,
+        buf: &[u8]) -> (String, Vec<u8>) {
+        let path = std::env::temp_dir().join(format!("rustycamera-{}-{}.y4m", name, std::process::id()));
+        let mut writer = Y4mWriter::create(&path, *fourcc, width, height, stride, (1, 30), chroma).unwrap();
+        writer.write_frame(buf).unwrap();
+        assert_eq!(writer.frames(), 1);
+        let len = writer.file_len();
+        writer.finish().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(bytes.len() as u64, len);
+
+        let header_end = bytes.iter().position(|&b| b == b'\n').unwrap() + 1;
+        let header = String::from_utf8(bytes[..header_end].to_vec()).unwrap();
+        assert_eq!(&bytes[header_end..header_end + 6], b"FRAME\n");
+
+        (header, bytes[header_end + 6..].to_vec())
+    }
+
+    #[test]
+    fn chroma_for_fourcc() {
+        assert_eq!(Chroma::for_fourcc(b"YUYV", false), Some(Chroma::C422));
+        assert_eq!(Chroma::for_fourcc(b"UYVY", true), Some(Chroma::C420Mpeg2));
+        assert_eq!(Chroma::for_fourcc(b"NV12", true), Some(Chroma::C420));
+        assert_eq!(Chroma::for_fourcc(b"NV21", false), Some(Chroma::C420));
+        assert_eq!(Chroma::for_fourcc(b"GREY", true), Some(Chroma::Mono));
+        assert_eq!(Chroma::for_fourcc(b"Y16 ", false), Some(Chroma::Mono16));
+        assert_eq!(Chroma::for_fourcc(b"MJPG", false), None);
+        assert_eq!(Chroma::for_fourcc(b"RGB3", false), None);
+    }
+
+    #[test]
+    fn packed_422() {
+        // 4x2, rows padded to 10 bytes
+        let buf = [
+            1, 10, 2, 20, 3, 11, 4, 21, 0, 0,
+            5, 12, 6, 22, 7, 13, 8, 23, 0, 0,
+        ];
+
+        let (header, planes) = write("yuyv", b"YUYV", 4, 2, 10, Chroma::C422, &buf);
+        assert_eq!(header, "YUV4MPEG2 W4 H2 F30:1 Ip A1:1 C422\n");
+        assert_eq!(planes, [1, 2, 3, 4, 5, 6, 7, 8, 10, 11, 12, 13, 20, 21, 22, 23]);
+
+        // rows of chroma averaged, rounding up
+        let (header, planes) = write("yuyv-420", b"YUYV", 4, 2, 10, Chroma::C420Mpeg2, &buf);
+        assert!(header.ends_with(" C420mpeg2\n"));
+        assert_eq!(planes, [1, 2, 3, 4, 5, 6, 7, 8, 11, 12, 21, 22]);
+
+        let uyvy = [10, 1, 20, 2];
+        let (_, planes) = write("uyvy", b"UYVY", 2, 1, 4, Chroma::C422, &uyvy);
+        assert_eq!(planes, [1, 2, 10, 20]);
+    }
+
+    #[test]
+    fn odd_width() {
+        // 3 pixels still take two pairs
+        let buf = [1, 10, 2, 20, 3, 11, 9, 21];
+        let (header, planes) = write("odd", b"YVYU", 3, 1, 8, Chroma::C422, &buf);
+        assert!(header.starts_with("YUV4MPEG2 W3 H1 "));
+        assert_eq!(planes, [1, 2, 3, 20, 21, 10, 11]);
+
+        // rows cut off right after the last pixel, which has no V of its own
+        let buf = [1, 10, 2, 20, 3, 11, 4, 12, 5, 22, 6, 13];
+        let (_, planes) = write("odd-tight", b"YUYV", 3, 2, 6, Chroma::C422, &buf);
+        assert_eq!(planes, [1, 2, 3, 4, 5, 6, 10, 11, 12, 13, 20, 20, 22, 22]);
+    }
+
+    #[test]
+    fn planar_420() {
+        // 2x2 luma with rows padded to 4 bytes, then chroma
+        let nv = [1, 2, 0, 0, 3, 4, 0, 0, 10, 20, 0, 0];
+        let (header, planes) = write("nv12", b"NV12", 2, 2, 4, Chroma::C420, &nv);
+        assert!(header.ends_with(" C420jpeg\n"));
+        assert_eq!(planes, [1, 2, 3, 4, 10, 20]);
+        let (_, planes) = write("nv21", b"NV21", 2, 2, 4, Chroma::C420, &nv);
+        assert_eq!(planes, [1, 2, 3, 4, 20, 10]);
+
+        let yuv = [1, 2, 0, 0, 3, 4, 0, 0, 10, 0, 20, 0];
+        let (_, planes) = write("yu12", b"YU12", 2, 2, 4, Chroma::C420, &yuv);
+        assert_eq!(planes, [1, 2, 3, 4, 10, 20]);
+        let (_, planes) = write("yv12", b"YV12", 2, 2, 4, Chroma::C420, &yuv);
+        assert_eq!(planes, [1, 2, 3, 4, 20, 10]);
+    }
+
+    #[test]
+    fn mono() {
+        let (header, planes) = write("grey", b"GREY", 2, 2, 3, Chroma::Mono, &[1, 2, 0, 3, 4, 0]);
+        assert!(header.ends_with(" Cmono\n"));
+        assert_eq!(planes, [1, 2, 3, 4]);
+
+        let (header, planes) = write("y16", b"Y16 ", 1, 2, 4, Chroma::Mono16, &[1, 2, 0, 0, 3, 4, 0, 0]);
+        assert!(header.ends_with(" Cmono16\n"));
+        assert_eq!(planes, [1, 2, 3, 4]);
+    }
+}